@@ -0,0 +1,105 @@
+use input::*;
+use memory::Mapper;
+use ppu::Ppu;
+use savestate::*;
+use std::any::Any;
+use std::io::Result;
+
+// See https://wiki.nesdev.com/w/index.php/Arkanoid_controller
+// The knob is read as an 8 bit value, about this far in either direction
+pub const KNOB_MIN: u8 = 0x62;
+pub const KNOB_MAX: u8 = 0xF2;
+
+// The NES version goes in port 2 with the button on bit 4 and the knob on bit 3 of $4017.
+// The Famicom version goes in the expansion port with the button on bit 1 of $4016 and the knob on bit 1 of $4017
+pub struct Arkanoid {
+    pub knob: u8,
+    pub button: bool,
+    famicom: bool,
+
+    strobe: bool,
+    shift: u8,
+}
+
+impl Arkanoid {
+    pub fn new() -> Arkanoid {
+        Arkanoid::with_variant(false)
+    }
+
+    pub fn new_famicom() -> Arkanoid {
+        Arkanoid::with_variant(true)
+    }
+
+    fn with_variant(famicom: bool) -> Arkanoid {
+        Arkanoid {
+            knob: KNOB_MIN,
+            button: false,
+            famicom: famicom,
+
+            strobe: false,
+            shift: 0,
+        }
+    }
+
+    // Turns the knob to follow an x position on screen
+    pub fn aim(&mut self, x: u32) {
+        self.knob = KNOB_MIN + (x.min(255) * (KNOB_MAX - KNOB_MIN) as u32 / 255) as u8;
+    }
+
+    // The knob position comes out inverted, most significant bit first
+    fn shift_out(&mut self) -> u8 {
+        let bit = !self.shift >> 7;
+        if !self.strobe {
+            self.shift <<= 1;
+        }
+        bit
+    }
+}
+
+impl InputDevice for Arkanoid {
+    fn name(&self) -> &'static str {
+        if self.famicom { "famicom arkanoid controller" } else { "arkanoid controller" }
+    }
+
+    fn read(&mut self, addr: u16, _: &mut Ppu, _: &mut Box<Mapper>) -> u8 {
+        let button = self.button as u8;
+
+        if self.famicom {
+            match addr {
+                0x4016 => button << 1,
+                _ => self.shift_out() << 1,
+            }
+        } else {
+            (button << 4) | (self.shift_out() << 3)
+        }
+    }
+
+    fn write(&mut self, val: u8) {
+        self.strobe = val&0b0000001>0;
+        if self.strobe {
+            self.shift = self.knob;
+        }
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+// The knob and button come from the frontend, so only the latched position is saved
+impl SaveState for Arkanoid {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_bool(self.strobe);
+        out.write_u8(self.shift);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        self.strobe = input.read_bool()?;
+        self.shift = input.read_u8()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,49 @@
+use sdl2::Sdl;
+use sdl2::audio::*;
+use nes_emulator::sound::SAMPLES_PER_SECOND;
+
+// Plays the samples the emulator made for each frame
+pub struct AudioOutput {
+    queue: AudioQueue<u8>,
+    pub muted: bool,
+    // Percent
+    volume: u32,
+    // The host doesn't run frames at exactly 60Hz, so past this audio is dropped instead of lagging further behind
+    max_queued_samples: u32,
+}
+
+impl AudioOutput {
+    // Latency is in milliseconds
+    pub fn new(sdl: &Sdl, volume: u32, latency: u32) -> AudioOutput {
+        let audio = sdl.audio().unwrap();
+
+        let desired_spec = AudioSpecDesired {
+            freq: Some(SAMPLES_PER_SECOND as i32),
+            channels: Some(1), // mono
+            samples: Some(512)
+        };
+
+        let queue = audio.open_queue::<u8, _>(None, &desired_spec).unwrap();
+        queue.resume();
+
+        AudioOutput {
+            queue: queue,
+            muted: false,
+            volume: volume,
+            max_queued_samples: SAMPLES_PER_SECOND * latency / 1000,
+        }
+    }
+
+    pub fn play(&mut self, samples: &[u8]) {
+        if self.muted || self.queue.size() > self.max_queued_samples {
+            return;
+        }
+
+        if self.volume == 100 {
+            self.queue.queue(samples);
+        } else {
+            let scaled: Vec<u8> = samples.iter().map(|&sample| (sample as u32 * self.volume / 100) as u8).collect();
+            self.queue.queue(&scaled);
+        }
+    }
+}
@@ -0,0 +1,114 @@
+extern crate nes_emulator;
+extern crate rand;
+
+use nes_emulator::desync::*;
+use nes_emulator::movie::Movie;
+use nes_emulator::power_on::RamFill;
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
+use std::env;
+use std::process;
+
+const USAGE: &str = "Usage: desync <rom> [movie.fm2] [--frames N] [--reload N] [--ram-fill FILL]
+
+Runs two copies of the rom side by side with the same input, and reports the first frame
+and component where they differ. Input comes from the movie, or from random presses if
+there is none. With --reload N the second copy is moved into a new machine through a save
+state every N frames, which finds state that save states miss. --ram-fill sets what ram holds at
+power on, as zeros, ff, alternating or random:<seed>, and defaults to the movie's or zeros.";
+
+fn parse_number(args: &mut Iterator<Item=String>, name: &str) -> u64 {
+    match args.next().and_then(|val| val.parse().ok()) {
+        Some(val) => val,
+        None => {
+            println!("{} needs a number\n\n{}", name, USAGE);
+            process::exit(2);
+        }
+    }
+}
+
+fn main() {
+    let mut rom_path = None;
+    let mut movie_path = None;
+    let mut frames = None;
+    let mut reload_interval = 0;
+    let mut ram_fill = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => frames = Some(parse_number(&mut args, "--frames")),
+            "--reload" => reload_interval = parse_number(&mut args, "--reload"),
+            "--ram-fill" => match args.next().as_ref().and_then(|val| RamFill::parse(val)) {
+                Some(fill) => ram_fill = Some(fill),
+                None => {
+                    println!("--ram-fill needs zeros, ff, alternating, random or random:<seed>\n\n{}", USAGE);
+                    process::exit(2);
+                }
+            },
+            _ if rom_path.is_none() => rom_path = Some(arg),
+            _ if movie_path.is_none() => movie_path = Some(arg),
+            _ => {
+                println!("{}", USAGE);
+                process::exit(2);
+            }
+        }
+    }
+
+    let rom_path = match rom_path {
+        Some(path) => path,
+        None => {
+            println!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    let movie = movie_path.map(|path| Movie::load(&path).unwrap_or_else(|e| {
+        println!("Could not load movie {}: {}", path, e);
+        process::exit(1);
+    }));
+    let frames = frames.unwrap_or(movie.as_ref().map_or(60*60, |movie| movie.frames.len() as u64));
+
+    let ram_fill = ram_fill.or_else(|| movie.as_ref().and_then(|movie| movie.ram_fill)).unwrap_or(RamFill::Zeros);
+    if let RamFill::Random(_) = ram_fill {
+        println!("Power on ram is {}", ram_fill);
+    }
+
+    let mut checker = DesyncChecker::new(&rom_path, ram_fill, reload_interval).unwrap_or_else(|e| {
+        println!("Could not load {}: {}", rom_path, e);
+        process::exit(1);
+    });
+
+    if let Some(state) = movie.as_ref().and_then(|movie| movie.savestate.as_ref()) {
+        for nes in [&mut checker.expected, &mut checker.actual].iter_mut() {
+            nes.load_state(state).unwrap_or_else(|e| {
+                println!("Could not load the movie's save state: {}", e);
+                process::exit(1);
+            });
+        }
+    }
+
+    let mut rng = XorShiftRng::from_seed([0x4E; 16]);
+    let mut random_buttons = 0;
+
+    for i in 0..frames {
+        let ports = match movie {
+            Some(ref movie) => movie.frames.get(i as usize).map_or([0, 0], |frame| frame.ports),
+            None => {
+                // Hold each random combination for a few frames, so games actually respond to it
+                if i % 8 == 0 {
+                    random_buttons = rng.gen::<u8>();
+                }
+                [random_buttons, 0]
+            }
+        };
+
+        if let Some(desync) = checker.step(ports) {
+            println!("Desync at frame {} in {} at offset {}: expected {:?}, got {:?}",
+                     desync.frame, desync.component, desync.offset, desync.expected, desync.actual);
+            process::exit(1);
+        }
+    }
+
+    println!("No desync in {} frames", frames);
+}
@@ -0,0 +1,94 @@
+pub const USAGE: &str = "Usage: nes_emulator [rom] [options]
+
+Options:
+  --scale N              Window size as a multiple of 256x240
+  --fullscreen           Start fullscreen
+  --mute                 No audio
+  --region REGION        Only ntsc is supported
+  --palette PALETTE      classic, ntsc, rgb or a .pal file with 64 or 512 colours
+  --power-on FILL        Ram contents at power on: zeros, ff, alternating, random or random:SEED
+  --load-state FILE      Start from a save state
+  --movie FILE           Play an fm2 movie from the start
+  --trace                Print every instruction the cpu runs
+  --headless             Run without a window, needs --frames
+  --frames N             Run N frames and quit, with --headless
+  --screenshot FILE      Save the last frame as a png, with --headless
+  --info                 Print what the rom header says and quit
+  --section.key=value    Override any setting from settings.ini
+  --help                 Show this
+
+The rom and the options that aren't given come from settings.ini.";
+
+#[derive(Debug, Default)]
+pub struct Options {
+    // Settings overrides as section.key=value, with the rom and the options that are settings
+    pub overrides: Vec<String>,
+    pub fullscreen: bool,
+    pub load_state: Option<String>,
+    pub movie: Option<String>,
+    pub trace: bool,
+    pub headless: bool,
+    pub frames: Option<u64>,
+    pub screenshot: Option<String>,
+    pub info: bool,
+    pub help: bool,
+}
+
+fn value(args: &mut Iterator<Item=String>, name: &str) -> Result<String, String> {
+    args.next().ok_or_else(|| format!("{} needs a value", name))
+}
+
+pub fn parse(args: &mut Iterator<Item=String>) -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut rom = None;
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--scale" => {
+                let scale = value(args, "--scale")?;
+                options.overrides.push(format!("video.scale={}", scale));
+            },
+            "--fullscreen" => options.fullscreen = true,
+            "--mute" => options.overrides.push("audio.volume=0".to_string()),
+            "--region" => {
+                let region = value(args, "--region")?;
+                options.overrides.push(format!("general.region={}", region));
+            },
+            "--palette" => {
+                let palette = value(args, "--palette")?;
+                options.overrides.push(format!("video.palette={}", palette));
+            },
+            "--power-on" => {
+                let fill = value(args, "--power-on")?;
+                options.overrides.push(format!("general.power_on={}", fill));
+            },
+            "--load-state" => options.load_state = Some(value(args, "--load-state")?),
+            "--movie" => options.movie = Some(value(args, "--movie")?),
+            "--trace" => options.trace = true,
+            "--headless" => options.headless = true,
+            "--frames" => {
+                let frames = value(args, "--frames")?;
+                options.frames = Some(frames.parse().map_err(|_| format!("--frames needs a number, not {}", frames))?);
+            },
+            "--screenshot" => options.screenshot = Some(value(args, "--screenshot")?),
+            "--info" => options.info = true,
+            "--help" | "-h" => options.help = true,
+            _ if arg.starts_with("--") && arg.contains('=') => options.overrides.push(arg[2..].to_string()),
+            _ if arg.starts_with('-') => return Err(format!("Unknown option {}", arg)),
+            _ if rom.is_none() => rom = Some(arg),
+            _ => return Err(format!("Only one rom can be given, not {}", arg)),
+        }
+    }
+
+    if options.headless && options.frames.is_none() {
+        return Err("--headless needs --frames".to_string());
+    }
+    if !options.headless && (options.frames.is_some() || options.screenshot.is_some()) {
+        return Err("--frames and --screenshot need --headless".to_string());
+    }
+
+    if let Some(rom) = rom {
+        options.overrides.push(format!("general.rom={}", rom));
+    }
+    Ok(options)
+}
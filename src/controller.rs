@@ -1,4 +1,19 @@
-use memory::*;
+use input::*;
+use memory::Mapper;
+use ppu::Ppu;
+use savestate::*;
+use std::any::Any;
+use std::io::Result;
+
+// Bits of a controller report, in the order the buttons are read out
+pub const BUTTON_A: u8 = 0b0000_0001;
+pub const BUTTON_B: u8 = 0b0000_0010;
+pub const BUTTON_SELECT: u8 = 0b0000_0100;
+pub const BUTTON_START: u8 = 0b0000_1000;
+pub const BUTTON_UP: u8 = 0b0001_0000;
+pub const BUTTON_DOWN: u8 = 0b0010_0000;
+pub const BUTTON_LEFT: u8 = 0b0100_0000;
+pub const BUTTON_RIGHT: u8 = 0b1000_0000;
 
 pub struct Controller {
     pub up: bool,
@@ -29,10 +44,84 @@ impl Controller {
             count: 0,
         }
     }
+
+    pub fn buttons(&self) -> u8 {
+        (self.a as u8)
+            | ((self.b as u8)<<1)
+            | ((self.select as u8)<<2)
+            | ((self.start as u8)<<3)
+            | ((self.up as u8)<<4)
+            | ((self.down as u8)<<5)
+            | ((self.left as u8)<<6)
+            | ((self.right as u8)<<7)
+    }
+
+    pub fn set_buttons(&mut self, buttons: u8) {
+        self.a = buttons&BUTTON_A != 0;
+        self.b = buttons&BUTTON_B != 0;
+        self.select = buttons&BUTTON_SELECT != 0;
+        self.start = buttons&BUTTON_START != 0;
+        self.up = buttons&BUTTON_UP != 0;
+        self.down = buttons&BUTTON_DOWN != 0;
+        self.left = buttons&BUTTON_LEFT != 0;
+        self.right = buttons&BUTTON_RIGHT != 0;
+    }
 }
 
-impl Mem for Controller {
-    fn read(&mut self, _: &mut Box<Mapper>, _: u16) -> u8 {
+// Turbo buttons are pressed for on_frames and released for off_frames while held, and autofire
+// buttons the same while toggled on. The rhythm follows the console frame count, so the same input
+// always gives the same presses and movies record them as ordinary buttons
+pub struct Turbo {
+    pub held: u8,
+    pub autofire: u8,
+    pub on_frames: u32,
+    pub off_frames: u32,
+
+    applied: u8,
+}
+
+impl Turbo {
+    pub fn new(on_frames: u32, off_frames: u32) -> Turbo {
+        Turbo {
+            held: 0,
+            autofire: 0,
+            on_frames: on_frames.max(1),
+            off_frames: off_frames,
+            applied: 0,
+        }
+    }
+
+    // Returns whether autofire is now on for the buttons
+    pub fn toggle_autofire(&mut self, buttons: u8) -> bool {
+        self.autofire ^= buttons;
+        self.autofire & buttons != 0
+    }
+
+    pub fn pressed(&self, frame: u64) -> u8 {
+        let period = (self.on_frames + self.off_frames) as u64;
+        if frame % period < self.on_frames as u64 {
+            self.held | self.autofire
+        } else {
+            0
+        }
+    }
+
+    // Call before each frame with the controller's buttons, and give it the returned ones.
+    // The turbo presses from the previous frame are taken out first
+    pub fn apply(&mut self, frame: u64, buttons: u8) -> u8 {
+        let pressed = self.pressed(frame);
+        let buttons = (buttons & !self.applied) | pressed;
+        self.applied = pressed;
+        buttons
+    }
+}
+
+impl InputDevice for Controller {
+    fn name(&self) -> &'static str {
+        "controller"
+    }
+
+    fn read(&mut self, _: u16, _: &mut Ppu, _: &mut Box<Mapper>) -> u8 {
         let res = if self.strobe {
             self.a
         }
@@ -57,8 +146,34 @@ impl Mem for Controller {
         if res { 1 } else { 0 }
     }
 
-    fn write(&mut self, _: &mut Box<Mapper>, _: u16, val: u8) {
+    fn write(&mut self, val: u8) {
         self.strobe = val&0b0000001>0;
         self.count = 0;
     }
-}
\ No newline at end of file
+
+    fn controller(&mut self, index: usize) -> Option<&mut Controller> {
+        if index == 0 { Some(self) } else { None }
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+// The buttons come from the frontend, so only the shift register is saved
+impl SaveState for Controller {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_bool(self.strobe);
+        out.write_u8(self.count);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        self.strobe = input.read_bool()?;
+        self.count = input.read_u8()?;
+        Ok(())
+    }
+}
@@ -1,6 +1,8 @@
 use nes::Chipset;
 use phf::Map;
 use std::fmt;
+use std::io::Result;
+use savestate::*;
 
 enum AddressModeResult {
     Val(u8),
@@ -248,6 +250,8 @@ pub struct Cpu {
     pub debug: bool,
     nmi_waiting: bool,
     irq_waiting: bool,
+    // The opcode and address of the instruction that locked up the cpu
+    pub jammed_on: Option<(u8, u16)>,
 }
 
 fn immediate(cpu: &mut Cpu, mem: &mut Chipset, _: bool) -> AddressModeResult {
@@ -694,7 +698,14 @@ fn manual(cpu: &mut Cpu, mem: &mut Chipset, op: u8) {
         },
         0xF8 => cpu.decimal = true,
         0xD8 => cpu.decimal = false,
-        _ => panic!("Not implemented yet! Op: {:X} at {:X}, chipset {:?}", op, cpu.pc.wrapping_sub(1), mem.mapper)
+        _ => {
+            // Treat unknown opcodes like the KIL family and lock up, so a headless run can still report
+            let addr = cpu.pc.wrapping_sub(1);
+            if cpu.debug {
+                println!("Unknown opcode {:02X} at {:04X}", op, addr);
+            }
+            cpu.jammed_on = Some((op, addr));
+        }
     }
 }
 
@@ -768,9 +779,29 @@ impl Cpu {
             nmi_waiting: false,
             irq_waiting: false,
             decimal: false,
+            jammed_on: None,
         }
     }
 
+    // The reset sequence goes through the motions of an interrupt without writing to the stack
+    pub fn reset(&mut self, pc: u16) {
+        self.s = self.s.wrapping_sub(3);
+        self.irq_disable = true;
+        self.pc = pc;
+        self.count += 7;
+        self.nmi_waiting = false;
+        self.irq_waiting = false;
+        self.jammed_on = None;
+    }
+
+    pub fn get_pc(&self) -> u16 {
+        self.pc
+    }
+
+    pub fn get_s(&self) -> u8 {
+        self.s
+    }
+
     pub fn get_p(&self) -> u8 {
         ((self.negative as u8)<<7)
         + ((self.overflow as u8)<<6)
@@ -792,6 +823,11 @@ impl Cpu {
     }
 
     pub fn tick(&mut self, mem: &mut Chipset) {
+        if self.jammed_on.is_some() {
+            self.count += 2;
+            return;
+        }
+
         //println!("{:X}", self.pc);
         let op = mem.read(self.pc);
         self.pc = self.pc.wrapping_add(1);
@@ -840,6 +876,14 @@ impl Cpu {
         }
     }
 
+    pub fn jump(&mut self, pc: u16) {
+        self.pc = pc;
+    }
+
+    pub fn is_jammed(&self) -> bool {
+        self.jammed_on.is_some()
+    }
+
     pub fn nmi(&mut self) {
         self.nmi_waiting = true;
     }
@@ -848,4 +892,40 @@ impl Cpu {
         if self.debug { println!("irq waiting"); }
         self.irq_waiting = true;
     }
-}
\ No newline at end of file
+}
+
+impl SaveState for Cpu {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_u8(self.a);
+        out.write_u8(self.x);
+        out.write_u8(self.y);
+        out.write_u8(self.s);
+        out.write_u16(self.pc);
+        out.write_u8(self.get_p());
+        out.write_u32(self.count);
+        out.write_bool(self.nmi_waiting);
+        out.write_bool(self.irq_waiting);
+        let (op, addr) = self.jammed_on.unwrap_or((0, 0));
+        out.write_bool(self.jammed_on.is_some());
+        out.write_u8(op);
+        out.write_u16(addr);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        self.a = input.read_u8()?;
+        self.x = input.read_u8()?;
+        self.y = input.read_u8()?;
+        self.s = input.read_u8()?;
+        self.pc = input.read_u16()?;
+        let p = input.read_u8()?;
+        self.set_p(p);
+        self.count = input.read_u32()?;
+        self.nmi_waiting = input.read_bool()?;
+        self.irq_waiting = input.read_bool()?;
+        let jammed = input.read_bool()?;
+        let op = input.read_u8()?;
+        let addr = input.read_u16()?;
+        self.jammed_on = if jammed { Some((op, addr)) } else { None };
+        Ok(())
+    }
+}
@@ -0,0 +1,221 @@
+use savestate::*;
+use sound::CPU_CYCLES_PER_SECOND;
+use sound::SAMPLES_PER_SECOND;
+use std::fs;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+
+// A cassette as the levels of its audio signal, 1 above the middle and 0 below
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tape {
+    pub samples: Vec<u8>,
+    pub sample_rate: u32,
+}
+
+impl Tape {
+    pub fn load(path: &str) -> Result<Tape> {
+        Tape::from_wav(&fs::read(path)?)
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, self.to_wav())
+    }
+
+    // Takes uncompressed 8 or 16 bit wav files, only the first channel is used
+    pub fn from_wav(data: &[u8]) -> Result<Tape> {
+        if data.len() < 12 || &data[0..4] != b"RIFF" || &data[8..12] != b"WAVE" {
+            return Err(invalid("Not a wav file"));
+        }
+
+        let mut format = None;
+        let mut pos = 12;
+        while pos + 8 <= data.len() {
+            let id = &data[pos..pos + 4];
+            let len = read_u32(data, pos + 4) as usize;
+            let body = &data[pos + 8..data.len().min(pos + 8 + len)];
+
+            if id == b"fmt " {
+                if body.len() < 16 {
+                    return Err(invalid("Invalid wav format chunk"));
+                }
+                if read_u16(body, 0) != 1 {
+                    return Err(invalid("Only uncompressed wav files are supported"));
+                }
+                format = Some((read_u16(body, 2) as usize, read_u32(body, 4), read_u16(body, 14)));
+            } else if id == b"data" {
+                let (channels, sample_rate, bits) = format.ok_or_else(|| invalid("Wav data before its format"))?;
+                if channels == 0 || sample_rate == 0 {
+                    return Err(invalid("Invalid wav format chunk"));
+                }
+
+                let samples = match bits {
+                    8 => body.chunks(channels).map(|frame| (frame[0] >= 0x80) as u8).collect(),
+                    16 => body.chunks(2*channels).filter(|frame| frame.len() >= 2)
+                        .map(|frame| (frame[1] & 0x80 == 0) as u8).collect(),
+                    _ => return Err(invalid(format!("{} bit wav files are not supported", bits))),
+                };
+
+                return Ok(Tape {
+                    samples: samples,
+                    sample_rate: sample_rate,
+                });
+            }
+
+            // Chunks are padded to an even length
+            pos += 8 + len + (len & 1);
+        }
+
+        Err(invalid("Wav file has no data"))
+    }
+
+    // 8 bit mono
+    pub fn to_wav(&self) -> Vec<u8> {
+        let mut out = vec![];
+        out.extend_from_slice(b"RIFF");
+        write_u32(&mut out, 36 + self.samples.len() as u32);
+        out.extend_from_slice(b"WAVEfmt ");
+        write_u32(&mut out, 16);
+        write_u16(&mut out, 1);
+        write_u16(&mut out, 1);
+        write_u32(&mut out, self.sample_rate);
+        write_u32(&mut out, self.sample_rate);
+        write_u16(&mut out, 1);
+        write_u16(&mut out, 8);
+        out.extend_from_slice(b"data");
+        write_u32(&mut out, self.samples.len() as u32);
+        out.extend(self.samples.iter().map(|&level| if level != 0 { 0xC0 } else { 0x40 }));
+        if self.samples.len() % 2 == 1 {
+            out.push(0);
+        }
+        out
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TapeMode {
+    Stopped,
+    Playing,
+    Recording,
+}
+
+// The cassette deck on the Family BASIC keyboard. Programs are saved as a 1 bit signal written
+// to $4016 and loaded by reading the signal back, so the tape runs in emulated time
+// See https://wiki.nesdev.com/w/index.php/Family_BASIC_Data_Recorder
+pub struct DataRecorder {
+    pub tape: Tape,
+    pub mode: TapeMode,
+
+    position: usize,
+    clock: u64,
+    output: u8,
+}
+
+impl DataRecorder {
+    pub fn new() -> DataRecorder {
+        DataRecorder {
+            tape: Tape {
+                samples: vec![],
+                sample_rate: SAMPLES_PER_SECOND,
+            },
+            mode: TapeMode::Stopped,
+            position: 0,
+            clock: 0,
+            output: 0,
+        }
+    }
+
+    pub fn play(&mut self, tape: Tape) {
+        self.tape = tape;
+        self.mode = TapeMode::Playing;
+        self.position = 0;
+        self.clock = 0;
+    }
+
+    // Starts a new tape
+    pub fn record(&mut self) {
+        self.tape = Tape {
+            samples: vec![],
+            sample_rate: SAMPLES_PER_SECOND,
+        };
+        self.mode = TapeMode::Recording;
+        self.position = 0;
+        self.clock = 0;
+    }
+
+    pub fn stop(&mut self) {
+        self.mode = TapeMode::Stopped;
+    }
+
+    pub fn tick(&mut self, cycles: u32) {
+        if self.mode == TapeMode::Stopped {
+            return;
+        }
+
+        self.clock += cycles as u64 * self.tape.sample_rate as u64;
+        while self.clock >= CPU_CYCLES_PER_SECOND {
+            self.clock -= CPU_CYCLES_PER_SECOND;
+
+            match self.mode {
+                TapeMode::Recording => self.tape.samples.push(self.output),
+                _ => {
+                    self.position += 1;
+                    if self.position >= self.tape.samples.len() {
+                        self.mode = TapeMode::Stopped;
+                        return;
+                    }
+                },
+            }
+        }
+    }
+
+    // The level under the tape head, 0 unless the tape is playing
+    pub fn input(&self) -> u8 {
+        match self.mode {
+            TapeMode::Playing => *self.tape.samples.get(self.position).unwrap_or(&0),
+            _ => 0
+        }
+    }
+
+    pub fn set_output(&mut self, level: u8) {
+        self.output = level & 1;
+    }
+}
+
+// The tape itself and whether it is moving are up to the user, like the buttons of a controller
+impl SaveState for DataRecorder {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_u64(self.position as u64);
+        out.write_u64(self.clock);
+        out.write_u8(self.output);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        self.position = input.read_u64()? as usize;
+        self.clock = input.read_u64()?;
+        self.output = input.read_u8()?;
+        Ok(())
+    }
+}
+
+fn invalid<E: Into<Box<::std::error::Error + Send + Sync>>>(error: E) -> Error {
+    Error::new(ErrorKind::InvalidData, error)
+}
+
+fn read_u16(data: &[u8], pos: usize) -> u16 {
+    data[pos] as u16 | (data[pos + 1] as u16) << 8
+}
+
+fn read_u32(data: &[u8], pos: usize) -> u32 {
+    read_u16(data, pos) as u32 | (read_u16(data, pos + 2) as u32) << 16
+}
+
+fn write_u16(out: &mut Vec<u8>, val: u16) {
+    out.push(val as u8);
+    out.push((val >> 8) as u8);
+}
+
+fn write_u32(out: &mut Vec<u8>, val: u32) {
+    write_u16(out, val as u16);
+    write_u16(out, (val >> 16) as u16);
+}
@@ -0,0 +1,107 @@
+use ines::load_file;
+use nes::Nes;
+use power_on::RamFill;
+use savestate::*;
+use std::io::Result;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Desync {
+    pub frame: u64,
+    pub component: &'static str,
+    pub offset: usize,
+    pub expected: Option<u8>,
+    pub actual: Option<u8>,
+}
+
+fn save<T: SaveState + ?Sized>(component: &T) -> Vec<u8> {
+    let mut out = StateWriter::new();
+    component.save_state(&mut out);
+    out.into_bytes()
+}
+
+// Each part of the machine in its save state form, with the whole machine last to catch anything in between
+pub fn component_states(nes: &Nes) -> Vec<(&'static str, Vec<u8>)> {
+    let chipset = &nes.chipset;
+    vec![
+        ("cpu", save(&nes.cpu)),
+        ("ram", chipset.mem.ram.to_vec()),
+        ("ppu", save(&chipset.ppu)),
+        ("mapper", save(&*chipset.mapper)),
+        ("apu", save(&chipset.sound)),
+        ("port1", save(&*chipset.ports[0])),
+        ("port2", save(&*chipset.ports[1])),
+        ("expansion", save(&*chipset.expansion)),
+        ("machine", nes.save_state()),
+    ]
+}
+
+pub fn compare(expected: &Nes, actual: &Nes) -> Option<Desync> {
+    let expected_states = component_states(expected);
+    let actual_states = component_states(actual);
+
+    for (&(component, ref a), &(_, ref b)) in expected_states.iter().zip(actual_states.iter()) {
+        if a == b {
+            continue;
+        }
+
+        let offset = a.iter().zip(b.iter()).position(|(x, y)| x != y).unwrap_or(a.len().min(b.len()));
+        return Some(Desync {
+            frame: expected.frame,
+            component: component,
+            offset: offset,
+            expected: a.get(offset).cloned(),
+            actual: b.get(offset).cloned(),
+        });
+    }
+
+    None
+}
+
+// Runs two machines in lockstep with the same input. Unless everything the emulation depends on is
+// part of the rom, the input and the machine state, they drift apart
+pub struct DesyncChecker {
+    pub expected: Nes,
+    pub actual: Nes,
+
+    // Moves the second machine into a freshly made one through a save state this often, 0 for never
+    reload_interval: u64,
+    rom: (Vec<u8>, Vec<u8>, u8, usize, bool),
+    ram_fill: RamFill,
+}
+
+impl DesyncChecker {
+    // Both machines power on with the same ram contents
+    pub fn new(rom_path: &str, ram_fill: RamFill, reload_interval: u64) -> Result<DesyncChecker> {
+        let (flags, prg, chr) = load_file(rom_path)?;
+        let rom = (prg, chr, flags.mapper, flags.prg_ram_size, flags.horiz_mirroring);
+
+        Ok(DesyncChecker {
+            expected: make_nes(&rom, ram_fill),
+            actual: make_nes(&rom, ram_fill),
+            reload_interval: reload_interval,
+            rom: rom,
+            ram_fill: ram_fill,
+        })
+    }
+
+    pub fn step(&mut self, ports: [u8; 2]) -> Option<Desync> {
+        for nes in [&mut self.expected, &mut self.actual].iter_mut() {
+            nes.chipset.set_buttons(0, ports[0]);
+            nes.chipset.set_buttons(1, ports[1]);
+            nes.tick_headless();
+        }
+
+        if self.reload_interval > 0 && self.actual.frame % self.reload_interval == 0 {
+            let state = self.actual.save_state();
+            self.actual = make_nes(&self.rom, self.ram_fill);
+            self.actual.load_state(&state).expect("Could not load a state from the same rom");
+        }
+
+        compare(&self.expected, &self.actual)
+    }
+}
+
+fn make_nes(&(ref prg, ref chr, mapper, prg_ram_size, horiz_mirroring): &(Vec<u8>, Vec<u8>, u8, usize, bool),
+            ram_fill: RamFill) -> Nes {
+    Nes::with_ram_fill(prg.clone(), chr.clone(), mapper, prg_ram_size, horiz_mirroring, ram_fill)
+}
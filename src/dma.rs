@@ -0,0 +1,100 @@
+use cpu::Cpu;
+use nes::Chipset;
+use savestate::*;
+use std::io::Result;
+
+// The 2A03's dma unit, which halts the cpu to copy sprites to the ppu and to fetch dmc samples.
+// It alternates get (read) and put (write) cycles, with gets on odd cycles since power on.
+// See https://wiki.nesdev.com/w/index.php/DMA
+//
+// The cpu runs whole instructions, so a dma that becomes due partway through one starts after it
+pub struct Dma {
+    // The page written to $4014, copied before the next instruction
+    pub oam_page: Option<u8>,
+    // Frames are an odd number of cycles long, so the cycle count alone doesn't give the parity
+    pub frame_parity: u32,
+}
+
+impl Dma {
+    pub fn new() -> Dma {
+        Dma {
+            oam_page: None,
+            frame_parity: 0,
+        }
+    }
+
+    fn is_get_cycle(&self, cpu: &Cpu) -> bool {
+        (cpu.count + self.frame_parity) % 2 == 1
+    }
+}
+
+// Each cycle the cpu is halted, the rest of the machine keeps running
+fn step(chipset: &mut Chipset, cpu: &mut Cpu) {
+    cpu.count += 1;
+    chipset.sound.tick(cpu, &mut chipset.mapper);
+}
+
+// 513 cycles, plus one to line up when the halt lands on a put cycle, plus any dmc fetches in between
+pub fn oam_dma(chipset: &mut Chipset, cpu: &mut Cpu, page: u8) {
+    step(chipset, cpu);
+    if !chipset.dma.is_get_cycle(cpu) {
+        step(chipset, cpu);
+    }
+
+    let addr = (page as u16) << 8;
+    for i in 0..256 {
+        // The dmc takes priority for a get cycle, then the sprite copy needs another put to line up
+        if let Some(dmc_addr) = chipset.sound.dmc_fetch() {
+            let val = chipset.read(dmc_addr);
+            chipset.sound.dmc_fill(cpu, val);
+            step(chipset, cpu);
+            step(chipset, cpu);
+        }
+
+        let val = chipset.read(addr + i);
+        step(chipset, cpu);
+        chipset.ppu.write_main(&mut chipset.mapper, 0x2004, val, cpu);
+        step(chipset, cpu);
+    }
+}
+
+// A halt cycle, a dummy cycle, one more to line up with a get if needed, then the get. The halted
+// cpu repeats the read it was doing, so when the halt lands on a read of a register with side
+// effects that read happens twice. For $4016 and $4017 that clocks the controller an extra time
+// and a button is lost, for $2007 the vram address moves on twice
+pub fn dmc_dma(chipset: &mut Chipset, cpu: &mut Cpu, instruction_end: u32) {
+    let addr = match chipset.sound.dmc_fetch() {
+        Some(addr) => addr,
+        None => return,
+    };
+
+    let last_read = chipset.last_read;
+    let halted_on_last_read = chipset.sound.dmc_fetch_cycle() + 1 == instruction_end;
+    if halted_on_last_read && last_read >= 0x2000 && last_read <= 0x401F {
+        chipset.read(last_read);
+    }
+
+    step(chipset, cpu);
+    step(chipset, cpu);
+    if !chipset.dma.is_get_cycle(cpu) {
+        step(chipset, cpu);
+    }
+
+    let val = chipset.read(addr);
+    chipset.sound.dmc_fill(cpu, val);
+    step(chipset, cpu);
+}
+
+impl SaveState for Dma {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_bool(self.oam_page.is_some());
+        out.write_u8(self.oam_page.unwrap_or(0));
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        let requested = input.read_bool()?;
+        let page = input.read_u8()?;
+        self.oam_page = if requested { Some(page) } else { None };
+        Ok(())
+    }
+}
@@ -0,0 +1,128 @@
+use data_recorder::DataRecorder;
+use input::*;
+use memory::Mapper;
+use ppu::Ppu;
+use savestate::*;
+use std::any::Any;
+use std::io::Result;
+
+// The keys of each row, column 0 then column 1, in the order of $4017 bits 4 to 1
+// See https://wiki.nesdev.com/w/index.php/Family_BASIC_Keyboard
+pub const KEY_MATRIX: [[&str; 8]; 9] = [
+    ["]", "[", "RETURN", "F8", "STOP", "YEN", "RSHIFT", "KANA"],
+    [";", ":", "@", "F7", "^", "-", "/", "_"],
+    ["K", "L", "O", "F6", "0", "P", ",", "."],
+    ["J", "U", "I", "F5", "8", "9", "N", "M"],
+    ["H", "G", "Y", "F4", "6", "7", "V", "B"],
+    ["D", "R", "T", "F3", "4", "5", "C", "F"],
+    ["A", "S", "W", "F2", "3", "E", "Z", "X"],
+    ["CTR", "Q", "ESC", "F1", "2", "1", "GRPH", "LSHIFT"],
+    ["LEFT", "RIGHT", "UP", "CLR", "INS", "DEL", "SPACE", "DOWN"],
+];
+
+// Goes in the Famicom expansion port. A write to $4016 with bit 0 set goes back to row 0, and
+// the rows are scanned by toggling bit 1: column 0 and column 1 of a row, then the next row.
+// $4017 bits 1-4 read the keys of the selected half row, low when pressed.
+// Bit 2 enables the keyboard and doubles as the tape switch of the data recorder
+pub struct FamilyKeyboard {
+    pub recorder: DataRecorder,
+
+    // A half row of pressed keys for each row and column, in $4017 bit positions
+    pressed: [u8; 18],
+    row: u8,
+    column: u8,
+    enabled: bool,
+}
+
+impl FamilyKeyboard {
+    pub fn new() -> FamilyKeyboard {
+        FamilyKeyboard {
+            recorder: DataRecorder::new(),
+            pressed: [0; 18],
+            row: 0,
+            column: 0,
+            enabled: false,
+        }
+    }
+
+    // Keys are named as in KEY_MATRIX, returns false for a key the keyboard doesn't have
+    pub fn set_key(&mut self, name: &str, pressed: bool) -> bool {
+        for (row, keys) in KEY_MATRIX.iter().enumerate() {
+            if let Some(i) = keys.iter().position(|&key| key == name) {
+                let bit = 0b1_0000 >> (i % 4);
+                let half_row = &mut self.pressed[2*row + i/4];
+                if pressed {
+                    *half_row |= bit;
+                } else {
+                    *half_row &= !bit;
+                }
+                return true;
+            }
+        }
+        false
+    }
+}
+
+impl InputDevice for FamilyKeyboard {
+    fn name(&self) -> &'static str {
+        "family basic keyboard"
+    }
+
+    fn read(&mut self, addr: u16, _: &mut Ppu, _: &mut Box<Mapper>) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+
+        match addr {
+            0x4016 => self.recorder.input() << 1,
+            _ => match self.pressed.get(2*self.row as usize + self.column as usize) {
+                Some(&pressed) => !pressed & 0b1_1110,
+                None => 0b1_1110
+            }
+        }
+    }
+
+    fn write(&mut self, val: u8) {
+        let column = (val >> 1) & 1;
+        self.enabled = val & 0b100 != 0;
+        self.recorder.set_output(val);
+
+        if self.enabled {
+            if column == 0 && self.column == 1 {
+                self.row = (self.row + 1) % 10;
+            }
+            if val & 1 != 0 {
+                self.row = 0;
+            }
+        }
+        self.column = column;
+    }
+
+    fn tick(&mut self, cycles: u32) {
+        self.recorder.tick(cycles);
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+impl SaveState for FamilyKeyboard {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_u8(self.row);
+        out.write_u8(self.column);
+        out.write_bool(self.enabled);
+        self.recorder.save_state(out);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        self.row = input.read_u8()?;
+        self.column = input.read_u8()?;
+        self.enabled = input.read_bool()?;
+        self.recorder.load_state(input)
+    }
+}
@@ -0,0 +1,140 @@
+use controller::*;
+use input::*;
+use memory::Mapper;
+use ppu::Ppu;
+use savestate::*;
+use std::any::Any;
+use std::io::Result;
+
+// See https://wiki.nesdev.com/w/index.php/Four_player_adapters
+// The NES Four Score takes both ports, one of these goes in each. Each port reports its two
+// controllers followed by a signature, players 1 and 3 on $4016 and 2 and 4 on $4017
+pub struct FourScore {
+    pub controllers: [Controller; 2],
+    signature: u8,
+
+    strobe: bool,
+    count: u8,
+}
+
+impl FourScore {
+    pub fn new(port: usize) -> FourScore {
+        FourScore {
+            controllers: [Controller::new(), Controller::new()],
+            signature: if port == 0 { 0b0000_1000 } else { 0b0000_0100 },
+
+            strobe: false,
+            count: 0,
+        }
+    }
+
+    fn report_bit(&self, bit: u8) -> u8 {
+        match bit {
+            0..=7 => (self.controllers[0].buttons() >> bit) & 1,
+            8..=15 => (self.controllers[1].buttons() >> (bit - 8)) & 1,
+            16..=23 => (self.signature >> (bit - 16)) & 1,
+            _ => 1
+        }
+    }
+}
+
+impl InputDevice for FourScore {
+    fn name(&self) -> &'static str {
+        "four score"
+    }
+
+    fn read(&mut self, _: u16, _: &mut Ppu, _: &mut Box<Mapper>) -> u8 {
+        if self.strobe {
+            return self.report_bit(0);
+        }
+
+        let bit = self.report_bit(self.count);
+        if self.count < 24 {
+            self.count += 1;
+        }
+        bit
+    }
+
+    fn write(&mut self, val: u8) {
+        self.strobe = val&0b0000001>0;
+        self.count = 0;
+    }
+
+    fn controller(&mut self, index: usize) -> Option<&mut Controller> {
+        self.controllers.get_mut(index)
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+impl SaveState for FourScore {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_bool(self.strobe);
+        out.write_u8(self.count);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        self.strobe = input.read_bool()?;
+        self.count = input.read_u8()?;
+        Ok(())
+    }
+}
+
+// The Famicom adapters go in the expansion port instead, with players 3 and 4 on bit 1 of $4016 and $4017
+pub struct FamicomFourPlayerAdapter {
+    pub controllers: [Controller; 2],
+}
+
+impl FamicomFourPlayerAdapter {
+    pub fn new() -> FamicomFourPlayerAdapter {
+        FamicomFourPlayerAdapter {
+            controllers: [Controller::new(), Controller::new()],
+        }
+    }
+}
+
+impl InputDevice for FamicomFourPlayerAdapter {
+    fn name(&self) -> &'static str {
+        "famicom four player adapter"
+    }
+
+    fn read(&mut self, addr: u16, ppu: &mut Ppu, mapper: &mut Box<Mapper>) -> u8 {
+        let controller = &mut self.controllers[addr as usize - 0x4016];
+        controller.read(addr, ppu, mapper) << 1
+    }
+
+    fn write(&mut self, val: u8) {
+        self.controllers[0].write(val);
+        self.controllers[1].write(val);
+    }
+
+    fn controller(&mut self, index: usize) -> Option<&mut Controller> {
+        self.controllers.get_mut(index)
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+impl SaveState for FamicomFourPlayerAdapter {
+    fn save_state(&self, out: &mut StateWriter) {
+        self.controllers[0].save_state(out);
+        self.controllers[1].save_state(out);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        self.controllers[0].load_state(input)?;
+        self.controllers[1].load_state(input)
+    }
+}
@@ -0,0 +1,150 @@
+use sdl2::GameControllerSubsystem;
+use sdl2::Sdl;
+use sdl2::controller::{Axis, Button, GameController};
+use nes_emulator::controller::*;
+use nes_emulator::nes::Nes;
+use settings::Settings;
+
+const PLAYERS: usize = 4;
+
+struct Pad {
+    controller: GameController,
+    player: usize,
+    // What the pad pressed last poll, so the keyboard can still press the rest
+    buttons: u8,
+    turbo: u8,
+    guide: bool,
+}
+
+// Game controllers can come and go while running. Each new pad gets the player whose settings name it,
+// or the first player without one, and its guide button moves it on to the next player.
+// SDL maps every pad it knows to the same layout, named after an Xbox pad
+pub struct Gamepads {
+    subsystem: GameControllerSubsystem,
+    pads: Vec<Pad>,
+    joysticks: u32,
+
+    buttons: Vec<(Button, u8)>,
+    turbo: Vec<(Button, u8)>,
+    // How far the left stick has to move to press a direction, out of 32767
+    deadzone: i16,
+    players: Vec<String>,
+}
+
+impl Gamepads {
+    pub fn new(sdl: &Sdl, settings: &Settings) -> Gamepads {
+        Gamepads {
+            subsystem: sdl.game_controller().unwrap(),
+            pads: vec![],
+            joysticks: 0,
+
+            buttons: settings.gamepad_buttons.clone(),
+            turbo: settings.gamepad_turbo.clone(),
+            deadzone: settings.gamepad_deadzone,
+            players: settings.gamepad_players.clone(),
+        }
+    }
+
+    // Call once per frame, before running it and applying turbo
+    pub fn poll(&mut self, nes: &mut Nes, turbo: &mut [Turbo]) {
+        self.hot_plug(nes, turbo);
+
+        for pad in self.pads.iter_mut() {
+            let guide = pad.controller.button(Button::Guide);
+            if guide && !pad.guide {
+                release(nes, pad.player, pad.buttons);
+                turbo[pad.player].held &= !pad.turbo;
+                pad.buttons = 0;
+                pad.turbo = 0;
+                pad.player = (pad.player + 1) % PLAYERS;
+                println!("{} is now player {}", pad.controller.name(), pad.player + 1);
+            }
+            pad.guide = guide;
+
+            let buttons = read_buttons(&pad.controller, &self.buttons) | read_stick(&pad.controller, self.deadzone);
+            let held = nes.chipset.buttons(pad.player) & !pad.buttons;
+            nes.chipset.set_buttons(pad.player, held | buttons);
+            pad.buttons = buttons;
+
+            let turbo_buttons = read_buttons(&pad.controller, &self.turbo);
+            let player_turbo = &mut turbo[pad.player];
+            player_turbo.held = (player_turbo.held & !pad.turbo) | turbo_buttons;
+            pad.turbo = turbo_buttons;
+        }
+    }
+
+    fn hot_plug(&mut self, nes: &mut Nes, turbo: &mut [Turbo]) {
+        for pad in self.pads.iter().filter(|pad| !pad.controller.attached()) {
+            println!("{} for player {} disconnected", pad.controller.name(), pad.player + 1);
+            release(nes, pad.player, pad.buttons);
+            turbo[pad.player].held &= !pad.turbo;
+        }
+        self.pads.retain(|pad| pad.controller.attached());
+
+        // Opening the pads we have again is harmless, SDL hands out the same device
+        let joysticks = self.subsystem.num_joysticks().unwrap_or(0);
+        if joysticks == self.joysticks {
+            return;
+        }
+        self.joysticks = joysticks;
+
+        for index in 0..joysticks {
+            if !self.subsystem.is_game_controller(index) {
+                continue;
+            }
+            let controller = match self.subsystem.open(index) {
+                Ok(controller) => controller,
+                Err(e) => {
+                    println!("Could not open game controller {}: {:?}", index, e);
+                    continue;
+                }
+            };
+            if self.pads.iter().any(|pad| pad.controller.instance_id() == controller.instance_id()) {
+                continue;
+            }
+
+            let player = self.free_player(&controller.name());
+            println!("{} connected as player {}", controller.name(), player + 1);
+            self.pads.push(Pad {
+                controller: controller,
+                player: player,
+                buttons: 0,
+                turbo: 0,
+                guide: false,
+            });
+        }
+    }
+
+    fn free_player(&self, name: &str) -> usize {
+        let free: Vec<usize> = (0..PLAYERS).filter(|&player| self.pads.iter().all(|pad| pad.player != player)).collect();
+        let wanted = free.iter().cloned()
+            .find(|&player| !self.players[player].is_empty() && name.contains(&self.players[player]));
+        wanted.or(free.first().cloned()).unwrap_or(0)
+    }
+}
+
+fn read_buttons(controller: &GameController, mapping: &[(Button, u8)]) -> u8 {
+    let mut buttons = 0;
+    for &(button, nes_button) in mapping {
+        if controller.button(button) {
+            buttons |= nes_button;
+        }
+    }
+    buttons
+}
+
+fn read_stick(controller: &GameController, deadzone: i16) -> u8 {
+    let mut buttons = 0;
+    let x = controller.axis(Axis::LeftX);
+    let y = controller.axis(Axis::LeftY);
+    if x < -deadzone { buttons |= BUTTON_LEFT; }
+    if x > deadzone { buttons |= BUTTON_RIGHT; }
+    if y < -deadzone { buttons |= BUTTON_UP; }
+    if y > deadzone { buttons |= BUTTON_DOWN; }
+    buttons
+}
+
+fn release(nes: &mut Nes, player: usize, buttons: u8) {
+    let held = nes.chipset.buttons(player);
+    nes.chipset.set_buttons(player, held & !buttons);
+}
@@ -0,0 +1,81 @@
+use image;
+use ines::load_file;
+use nes::Nes;
+use ppu::{make_canvas, NesImageBuffer};
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+
+#[derive(Debug, PartialEq)]
+pub enum Comparison {
+    Matches,
+    Differs(u32),
+}
+
+// The script is a list of (frame, buttons), and controller 1 holds each set of buttons until the next entry
+pub fn run_frames(rom: &str, script: &[(u32, u8)], frames: u32) -> Result<NesImageBuffer> {
+    let (flags, prg, chr) = load_file(rom)?;
+
+    if !Nes::supports_mapper(flags.mapper) {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("Mapper {} is not supported", flags.mapper)));
+    }
+
+    let mut nes = Nes::new(prg, chr, flags.mapper, flags.prg_ram_size, flags.horiz_mirroring);
+
+    for frame in 0..frames {
+        for &(start, buttons) in script {
+            if start == frame {
+                nes.chipset.set_buttons(0, buttons);
+            }
+        }
+
+        nes.tick_headless();
+    }
+
+    Ok(nes.chipset.ppu.output_canvas.clone())
+}
+
+// On a mismatch, writes the reference, the frame and the differing pixels side by side to diff_path
+pub fn compare_with_reference(frame: &NesImageBuffer, reference_path: &str,
+                              diff_path: &str) -> Result<Comparison> {
+    let reference = image::open(reference_path)
+        .map_err(|e| Error::new(ErrorKind::InvalidData, format!("{}: {}", reference_path, e)))?
+        .to_rgba();
+
+    let w = frame.width();
+    let h = frame.height();
+    if reference.width() != w || reference.height() != h {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("{} is {}x{}, expected {}x{}", reference_path,
+                                      reference.width(), reference.height(), w, h)));
+    }
+
+    let mut diff = make_canvas(w * 3, h);
+    let mut differing = 0;
+
+    for y in 0..h {
+        for x in 0..w {
+            let expected = *reference.get_pixel(x, y);
+            let actual = *frame.get_pixel(x, y);
+
+            let marker = if expected == actual {
+                image::Rgba([actual[0]/4, actual[1]/4, actual[2]/4, 0xFF])
+            } else {
+                differing += 1;
+                image::Rgba([0xFF, 0, 0, 0xFF])
+            };
+
+            diff.put_pixel(x, y, expected);
+            diff.put_pixel(w + x, y, actual);
+            diff.put_pixel(2*w + x, y, marker);
+        }
+    }
+
+    if differing == 0 {
+        return Ok(Comparison::Matches);
+    }
+
+    diff.save(diff_path)?;
+    Ok(Comparison::Differs(differing))
+}
@@ -0,0 +1,81 @@
+use controller::Controller;
+use memory::Mapper;
+use ppu::Ppu;
+use savestate::*;
+use std::any::Any;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+
+// Something plugged into one of the controller ports or the Famicom expansion port.
+// Every device sees the writes to $4016, and puts its data bits on $4016/$4017 reads
+pub trait InputDevice: SaveState {
+    // Save states record it, so a state can't be loaded into a different device
+    fn name(&self) -> &'static str;
+
+    // Only the bits the device drives, the chipset adds the open bus ones.
+    // Light guns look at the picture, so they get the ppu
+    fn read(&mut self, addr: u16, ppu: &mut Ppu, mapper: &mut Box<Mapper>) -> u8;
+
+    fn write(&mut self, val: u8);
+
+    // CPU cycles since the last call, for devices that run in emulated time
+    fn tick(&mut self, _cycles: u32) {
+    }
+
+    // The standard controllers this device has, for adapters that take several
+    fn controller(&mut self, _index: usize) -> Option<&mut Controller> {
+        None
+    }
+
+    fn as_any(&self) -> &Any;
+    fn as_any_mut(&mut self) -> &mut Any;
+}
+
+pub struct Unplugged;
+
+impl InputDevice for Unplugged {
+    fn name(&self) -> &'static str {
+        "unplugged"
+    }
+
+    fn read(&mut self, _: u16, _: &mut Ppu, _: &mut Box<Mapper>) -> u8 {
+        0
+    }
+
+    fn write(&mut self, _: u8) {
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+impl SaveState for Unplugged {
+    fn save_state(&self, _: &mut StateWriter) {
+    }
+
+    fn load_state(&mut self, _: &mut StateReader) -> Result<()> {
+        Ok(())
+    }
+}
+
+pub fn save_device(device: &InputDevice, out: &mut StateWriter) {
+    out.write_vec(device.name().as_bytes());
+    device.save_state(out);
+}
+
+pub fn load_device(device: &mut InputDevice, input: &mut StateReader) -> Result<()> {
+    let name = input.read_vec()?;
+    if name != device.name().as_bytes() {
+        return Err(Error::new(ErrorKind::InvalidData,
+                              format!("Save state has a {} where a {} is plugged in",
+                                      String::from_utf8_lossy(&name), device.name())));
+    }
+
+    device.load_state(input)
+}
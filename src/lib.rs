@@ -0,0 +1,35 @@
+#![feature(plugin)]
+
+#![plugin(phf_macros)]
+extern crate phf;
+extern crate image;
+extern crate objekt;
+extern crate rand;
+
+pub mod cpu;
+pub mod ines;
+pub mod controller;
+pub mod input;
+pub mod zapper;
+pub mod four_score;
+pub mod arkanoid;
+pub mod power_pad;
+pub mod family_keyboard;
+pub mod data_recorder;
+pub mod nes;
+pub mod memory;
+pub mod dma;
+pub mod ppu;
+pub mod palette;
+pub mod sound;
+pub mod savestate;
+pub mod power_on;
+pub mod rewind;
+pub mod test_rom;
+pub mod golden_frame;
+pub mod md5;
+pub mod movie;
+pub mod desync;
+
+pub mod mapper_0;
+pub mod mapper_4;
@@ -1,7 +1,4 @@
-#![feature(plugin)]
-
-#![plugin(phf_macros)]
-extern crate phf;
+extern crate nes_emulator;
 extern crate piston;
 extern crate opengl_graphics;
 extern crate image;
@@ -9,32 +6,41 @@ extern crate graphics;
 extern crate piston_window;
 extern crate sdl2_window;
 extern crate sdl2;
-extern crate objekt;
 
 use piston::input::*;
+use std::mem;
 use std::time::Instant;
+use std::fs;
+use std::path::Path;
 use piston::window::WindowSettings;
 use opengl_graphics::OpenGL;
 use piston::event_loop::*;
 use piston_window::*;
 use sdl2_window::Sdl2Window;
 
-mod cpu;
-mod ines;
-mod controller;
-mod nes;
-mod memory;
-mod ppu;
 mod settings;
-mod sound;
-
-mod mapper_0;
-mod mapper_4;
+mod audio;
+mod gamepad;
+mod cli;
 
-use ines::*;
-use nes::*;
+use nes_emulator::ines::*;
+use nes_emulator::nes::*;
+use nes_emulator::controller::*;
+use nes_emulator::zapper::Zapper;
+use nes_emulator::input::{InputDevice, Unplugged};
+use nes_emulator::four_score::*;
+use nes_emulator::arkanoid::Arkanoid;
+use nes_emulator::power_pad::*;
+use nes_emulator::family_keyboard::FamilyKeyboard;
+use nes_emulator::data_recorder::*;
+use nes_emulator::rewind::RewindBuffer;
+use nes_emulator::movie::*;
+use nes_emulator::power_on::RamFill;
+use nes_emulator::palette::{Palette, BUILT_IN};
 use settings::*;
-use ppu::{make_canvas, NesImageBuffer};
+use audio::AudioOutput;
+use gamepad::Gamepads;
+use nes_emulator::ppu::{make_canvas, NesImageBuffer};
 
 trait ControllerMethod {
     fn do_input(&mut self, nes: &mut Nes, e: &Event);
@@ -42,44 +48,118 @@ trait ControllerMethod {
 
 struct User {
     dump_count: u8,
+    window_size: [u32; 2],
+    // (key, player, button) from the settings
+    keys: Vec<(Button, usize, u8)>,
+    // (key, mat button number)
+    power_pad_keys: Vec<(Button, u8)>,
+}
+
+impl User {
+    fn key_binding(&self, button: Button) -> Vec<(usize, u8)> {
+        self.keys.iter().filter(|binding| binding.0 == button).map(|&(_, player, button)| (player, button)).collect()
+    }
+
+    fn power_pad_key(&self, button: Button) -> Option<u8> {
+        self.power_pad_keys.iter().find(|binding| binding.0 == button).map(|binding| binding.1)
+    }
+}
+
+fn mat_plugged_in(nes: &mut Nes) -> bool {
+    nes.chipset.device::<PowerPad>(1).is_some() || nes.chipset.expansion_device::<FamilyTrainer>().is_some()
+}
+
+fn set_power_pad_button(nes: &mut Nes, number: u8, pressed: bool) {
+    let button = power_pad_button(number);
+    let update = |buttons: &mut u16| if pressed { *buttons |= button } else { *buttons &= !button };
+
+    if let Some(pad) = nes.chipset.device::<PowerPad>(1) {
+        update(&mut pad.buttons);
+    }
+    if let Some(mat) = nes.chipset.expansion_device::<FamilyTrainer>() {
+        update(&mut mat.buttons);
+    }
+}
+
+// The left mouse button is the zapper trigger and the arkanoid controller button
+fn set_fire(nes: &mut Nes, pressed: bool) {
+    if let Some(zapper) = nes.chipset.device::<Zapper>(1) {
+        zapper.trigger = pressed;
+    }
+    if let Some(arkanoid) = nes.chipset.device::<Arkanoid>(1) {
+        arkanoid.button = pressed;
+    }
+    if let Some(arkanoid) = nes.chipset.expansion_device::<Arkanoid>() {
+        arkanoid.button = pressed;
+    }
+}
+
+fn set_button(nes: &mut Nes, player: usize, button: u8, pressed: bool) {
+    let buttons = nes.chipset.buttons(player);
+    nes.chipset.set_buttons(player, if pressed { buttons | button } else { buttons & !button });
 }
 
 impl ControllerMethod for User {
     fn do_input(&mut self, nes: &mut Nes, e: &Event) {
+        if let Some(size) = e.resize_args() {
+            self.window_size = size;
+        }
+
+        // The mouse aims a zapper and turns an arkanoid knob, scaled from the window back to NES pixels
+        if let Some(pos) = e.mouse_cursor_args() {
+            let x = pos[0] * 256.0 / self.window_size[0] as f64;
+            let y = pos[1] * 240.0 / self.window_size[1] as f64;
+            if let Some(zapper) = nes.chipset.device::<Zapper>(1) {
+                zapper.aim = if x >= 0.0 && x < 256.0 && y >= 0.0 && y < 240.0 {
+                    Some((x as u32, y as u32))
+                } else {
+                    None
+                };
+            }
+            if let Some(arkanoid) = nes.chipset.device::<Arkanoid>(1) {
+                arkanoid.aim(x.max(0.0) as u32);
+            }
+            if let Some(arkanoid) = nes.chipset.expansion_device::<Arkanoid>() {
+                arkanoid.aim(x.max(0.0) as u32);
+            }
+        }
+
+        if let Some(Button::Mouse(MouseButton::Left)) = e.press_args() {
+            set_fire(nes, true);
+        }
+        if let Some(Button::Mouse(MouseButton::Left)) = e.release_args() {
+            set_fire(nes, false);
+        }
+
         if let Some(button) = e.press_args() {
             match button {
-                Button::Keyboard(Key::D) => nes.cpu.debug = true,
                 Button::Keyboard(Key::R) => {
                     if DEBUG {
                         write_bytes_to_file(format!("{}.bin", self.dump_count), &nes.chipset.mem.ram);
                         self.dump_count += 1;
                     }
                 },
-                Button::Keyboard(Key::Up) => nes.chipset.controller1.up = true,
-                Button::Keyboard(Key::Left) => nes.chipset.controller1.left = true,
-                Button::Keyboard(Key::Down) => nes.chipset.controller1.down = true,
-                Button::Keyboard(Key::Right) => nes.chipset.controller1.right = true,
-                Button::Keyboard(Key::A) => nes.chipset.controller1.a = true,
-                Button::Mouse(MouseButton::Left) => nes.chipset.controller1.a = true,
-                Button::Keyboard(Key::S) => nes.chipset.controller1.b = true,
-                Button::Keyboard(Key::Return) => nes.chipset.controller1.start = true,
-                Button::Keyboard(Key::Space) => nes.chipset.controller1.select = true,
                 _ => ()
             }
+
+            // While a mat is plugged in, its keys only press the mat
+            let mat_key = self.power_pad_key(button);
+            if mat_key.is_none() || !mat_plugged_in(nes) {
+                for (player, nes_button) in self.key_binding(button) {
+                    set_button(nes, player, nes_button, true);
+                }
+            }
+            if let Some(number) = mat_key {
+                set_power_pad_button(nes, number, true);
+            }
         }
 
         if let Some(button) = e.release_args() {
-            match button {
-                Button::Keyboard(Key::Up) => nes.chipset.controller1.up = false,
-                Button::Keyboard(Key::Left) => nes.chipset.controller1.left = false,
-                Button::Keyboard(Key::Down) => nes.chipset.controller1.down = false,
-                Button::Keyboard(Key::Right) => nes.chipset.controller1.right = false,
-                Button::Keyboard(Key::A) => nes.chipset.controller1.a = false,
-                Button::Mouse(MouseButton::Left) => nes.chipset.controller1.a = false,
-                Button::Keyboard(Key::S) => nes.chipset.controller1.b = false,
-                Button::Keyboard(Key::Return) => nes.chipset.controller1.start = false,
-                Button::Keyboard(Key::Space) => nes.chipset.controller1.select = false,
-                _ => ()
+            for (player, nes_button) in self.key_binding(button) {
+                set_button(nes, player, nes_button, false);
+            }
+            if let Some(number) = self.power_pad_key(button) {
+                set_power_pad_button(nes, number, false);
             }
         }
     }
@@ -89,16 +169,43 @@ struct App {
     nes: Nes,
     frames: u64,
     last_time: Instant,
+    rom_path: String,
+    state_slot: u8,
+    rewind: RewindBuffer,
+    rewinding: bool,
+    // Whether a state has been restored since rewind was pressed
+    rewound: bool,
+    rom_checksum: [u8; 16],
+    movie: Option<MoviePlayer>,
+    keyboard_captured: bool,
+    turbo: Vec<Turbo>,
+    // Reset and power presses wait for the next frame so that movies can record them
+    pending_commands: u8,
+    settings: Settings,
 
+    audio: AudioOutput,
+    gamepads: Gamepads,
     controller_method: Box<ControllerMethod>,
     texture: G2dTexture,
     canvas: NesImageBuffer,
 }
 
-fn emulate((flags, prg, chr) : (Flags, Vec<u8>, Vec<u8>), controller_method: Box<ControllerMethod>) {
+// With the palette from the settings
+fn make_nes((flags, prg, chr): (Flags, Vec<u8>, Vec<u8>), settings: &Settings, ram_fill: RamFill) -> Nes {
+    let mut nes = Nes::with_ram_fill(prg, chr, flags.mapper, flags.prg_ram_size, flags.horiz_mirroring, ram_fill);
+    if let RamFill::Random(_) = ram_fill {
+        println!("Power on ram is {}", ram_fill);
+    }
+    nes.chipset.ppu.sprite_limit = settings.sprite_limit;
+    nes.chipset.ppu.palette = load_palette(settings);
+    nes
+}
+
+fn emulate(rom_path: &str, (flags, prg, chr) : (Flags, Vec<u8>, Vec<u8>), controller_method: Box<ControllerMethod>,
+           settings: Settings, options: &cli::Options) {
     println!("Loaded rom with {:?}", flags);
 
-    let size = [256*4, 240*4];
+    let size = settings.window_size();
 
     let sdl = sdl2::init().unwrap();
     let video_subsystem = sdl.video().unwrap();
@@ -109,9 +216,12 @@ fn emulate((flags, prg, chr) : (Flags, Vec<u8>, Vec<u8>), controller_method: Box
             .opengl(OpenGL::V3_2)
             .vsync(false)
             .exit_on_esc(true)
+            .fullscreen(options.fullscreen)
     ).unwrap());
 
-    let nes = Nes::new(prg, chr, flags.mapper, flags.prg_ram_size, flags.horiz_mirroring, sdl);
+    let checksum = rom_checksum(&prg, &chr);
+    let mut nes = make_nes((flags, prg, chr), &settings, settings.ram_fill);
+    nes.cpu.debug = options.trace;
 
     let canvas = make_canvas(size[0], size[1]);
     let tex = Texture::from_image(&mut window.factory, &canvas, &TextureSettings::new()).unwrap();
@@ -120,12 +230,36 @@ fn emulate((flags, prg, chr) : (Flags, Vec<u8>, Vec<u8>), controller_method: Box
         nes: nes,
         frames: 0,
         last_time:Instant::now(),
+        rom_path: rom_path.to_string(),
+        state_slot: 0,
+        rewind: RewindBuffer::new(settings.rewind_interval, settings.rewind_budget),
+        rewinding: false,
+        rewound: false,
+        rom_checksum: checksum,
+        movie: None,
+        keyboard_captured: false,
+        turbo: (0..4).map(|_| Turbo::new(settings.turbo_on_frames, settings.turbo_off_frames)).collect(),
+        pending_commands: 0,
+
+        audio: AudioOutput::new(&sdl, settings.volume, settings.latency),
+        gamepads: Gamepads::new(&sdl, &settings),
+        settings: settings,
         controller_method: controller_method,
 
         texture: tex,
         canvas: canvas,
     };
 
+    if let Some(ref path) = options.load_state {
+        match fs::read(path).and_then(|data| app.nes.load_state(&data)) {
+            Ok(()) => println!("Loaded state from {}", path),
+            Err(e) => println!("Could not load state from {}: {}", path, e),
+        }
+    }
+    if let Some(ref path) = options.movie {
+        start_playback(&mut app, path);
+    }
+
     let mut events = Events::new({
         let mut es = EventSettings::new();
         es.max_fps = 60;
@@ -143,7 +277,11 @@ fn handle_event(window: &mut PistonWindow<Sdl2Window>, e: Event, app: &mut App)
         app.texture = Texture::from_image(&mut window.factory,&app.canvas, &TextureSettings::new()).unwrap();
     }
 
-    app.controller_method.as_mut().do_input(&mut app.nes, &e);
+    if !do_family_keyboard(app, &e) {
+        do_hotkeys(app, &e);
+        do_turbo(app, &e);
+        app.controller_method.as_mut().do_input(&mut app.nes, &e);
+    }
 
     if let Some(_args) = e.update_args() {
         app.frames += 1;
@@ -156,7 +294,30 @@ fn handle_event(window: &mut PistonWindow<Sdl2Window>, e: Event, app: &mut App)
             app.last_time = Instant::now();
         }
 
-        app.nes.tick();
+        if app.rewinding {
+            // Show the frame after each restored state, so the picture steps back as well
+            if app.rewind.rewind(&mut app.nes) {
+                if let Some(ref mut player) = app.movie {
+                    if app.rewound {
+                        player.rewound(app.nes.frame);
+                    } else {
+                        player.state_loaded(app.nes.frame);
+                    }
+                }
+                app.rewound = true;
+                let commands = apply_movie_input(app);
+                run_commands(&mut app.nes, commands);
+                app.nes.tick();
+            }
+        } else {
+            app.gamepads.poll(&mut app.nes, &mut app.turbo);
+            apply_turbo(app);
+            let commands = apply_movie_input(app);
+            run_commands(&mut app.nes, commands);
+            app.nes.tick();
+            app.rewind.capture(&app.nes);
+            app.audio.play(app.nes.chipset.sound.samples());
+        }
         app.nes.prepare_draw(&mut app.canvas);
     }
 
@@ -172,13 +333,537 @@ fn handle_event(window: &mut PistonWindow<Sdl2Window>, e: Event, app: &mut App)
     }
 }
 
-fn main() {
-    let input: Box<ControllerMethod> = Box::new(User { dump_count: 0 });
-    match load_file("assets/smb3.nes") {
-//    match load_file("assets/SNDTEST.NES") {
-        Ok(rom) => emulate(rom, input),
-        Err(e) => panic!("Error: {:?}", e)
+fn do_turbo(app: &mut App, e: &Event) {
+    if let Some(button) = e.press_args() {
+        for &(key, player, nes_button) in &app.settings.turbo_keys {
+            if key == button {
+                app.turbo[player].held |= nes_button;
+            }
+        }
+        for &(key, player, nes_button) in &app.settings.autofire_keys {
+            if key == button {
+                let on = app.turbo[player].toggle_autofire(nes_button);
+                println!("Autofire {} for player {}", if on { "on" } else { "off" }, player + 1);
+            }
+        }
+    }
+
+    if let Some(button) = e.release_args() {
+        for &(key, player, nes_button) in &app.settings.turbo_keys {
+            if key == button {
+                app.turbo[player].held &= !nes_button;
+            }
+        }
+    }
+}
+
+// Before the movie, so that turbo presses are recorded like any others
+fn apply_turbo(app: &mut App) {
+    for (player, turbo) in app.turbo.iter_mut().enumerate() {
+        let buttons = app.nes.chipset.buttons(player);
+        app.nes.chipset.set_buttons(player, turbo.apply(app.nes.frame, buttons));
+    }
+}
+
+// While a movie is active it decides what the controllers see, after recording the user's input if needed.
+// Returns the reset and power commands for this frame
+fn apply_movie_input(app: &mut App) -> u8 {
+    let live_commands = app.pending_commands;
+    app.pending_commands = 0;
+
+    let player = match app.movie {
+        Some(ref mut player) => player,
+        None => return live_commands
+    };
+
+    let live = MovieFrame {
+        commands: live_commands,
+        ports: [app.nes.chipset.buttons(0), app.nes.chipset.buttons(1)],
+    };
+
+    let was_playing = player.mode == MovieMode::Playing;
+    let frame = player.next_frame(app.nes.frame, live);
+
+    if was_playing && player.mode == MovieMode::Finished {
+        println!("Movie finished after {} frames", player.movie.frames.len());
+        app.nes.chipset.set_buttons(0, 0);
+        app.nes.chipset.set_buttons(1, 0);
+        return 0;
+    }
+
+    app.nes.chipset.set_buttons(0, frame.ports[0]);
+    app.nes.chipset.set_buttons(1, frame.ports[1]);
+    frame.commands
+}
+
+fn run_commands(nes: &mut Nes, commands: u8) {
+    if commands & COMMAND_POWER != 0 {
+        nes.power_cycle();
+    } else if commands & COMMAND_SOFT_RESET != 0 {
+        nes.reset();
+    }
+}
+
+// .pal files are RGB triples, 64 colours with the emphasis worked out or all 512 with it
+// The ntsc palette comes out of the generator with the settings' adjustments
+fn load_palette(settings: &Settings) -> Palette {
+    let name = &settings.palette;
+    if name == "ntsc" {
+        return Palette::ntsc(&settings.ntsc);
+    }
+    if let Some(palette) = Palette::built_in(name) {
+        return palette;
+    }
+    match Palette::load(name) {
+        Ok(palette) => palette,
+        Err(e) => {
+            println!("Could not load palette {}: {}", name, e);
+            Palette::default()
+        }
+    }
+}
+
+fn next_palette(app: &mut App) {
+    let next = match BUILT_IN.iter().position(|&name| name == app.settings.palette) {
+        Some(i) => BUILT_IN[(i + 1) % BUILT_IN.len()],
+        None => BUILT_IN[0],
+    };
+    app.settings.palette = next.to_string();
+    app.nes.chipset.ppu.palette = load_palette(&app.settings);
+    app.nes.chipset.ppu.update_canvas();
+    println!("Palette {}", next);
+}
+
+fn power_on(app: &mut App, ram_fill: RamFill) -> std::io::Result<()> {
+    let rom = load_file(&app.rom_path)?;
+    let mut nes = make_nes(rom, &app.settings, ram_fill);
+    // Whatever is plugged in stays plugged in
+    mem::swap(&mut nes.chipset.ports, &mut app.nes.chipset.ports);
+    mem::swap(&mut nes.chipset.expansion, &mut app.nes.chipset.expansion);
+    app.nes = nes;
+    app.rewind.clear();
+    Ok(())
+}
+
+fn next_port_2_device(current: &str) -> Box<InputDevice> {
+    match current {
+        "controller" => Box::new(Zapper::new()),
+        "zapper" => Box::new(Arkanoid::new()),
+        "arkanoid controller" => Box::new(PowerPad::new()),
+        _ => Box::new(Controller::new()),
+    }
+}
+
+fn next_expansion_device(current: &str) -> Box<InputDevice> {
+    match current {
+        "unplugged" => Box::new(FamicomFourPlayerAdapter::new()),
+        "famicom four player adapter" => Box::new(Arkanoid::new_famicom()),
+        "famicom arkanoid controller" => Box::new(FamilyTrainer::new()),
+        "family trainer" => Box::new(FamilyKeyboard::new()),
+        _ => Box::new(Unplugged),
+    }
+}
+
+fn swap_port_2(app: &mut App) {
+    let device = next_port_2_device(app.nes.chipset.ports[1].name());
+    println!("Plugged a {} into port 2", device.name());
+    app.nes.chipset.plug(1, device);
+}
+
+fn swap_expansion(app: &mut App) {
+    let device = next_expansion_device(app.nes.chipset.expansion.name());
+    println!("Plugged a {} into the expansion port", device.name());
+    if device.name() == "family basic keyboard" {
+        println!("Scroll lock switches the keyboard between typing on it and the hotkeys");
+    }
+    app.nes.chipset.plug_expansion(device);
+}
+
+fn swap_four_score(app: &mut App) {
+    let chipset = &mut app.nes.chipset;
+
+    if chipset.device::<FourScore>(0).is_some() {
+        chipset.plug(0, Box::new(Controller::new()));
+        chipset.plug(1, Box::new(Controller::new()));
+        println!("Unplugged the four score");
+    } else {
+        chipset.plug(0, Box::new(FourScore::new(0)));
+        chipset.plug(1, Box::new(FourScore::new(1)));
+        println!("Plugged in a four score");
+    }
+}
+
+fn movie_path(app: &App) -> String {
+    format!("{}.fm2", app.rom_path)
+}
+
+// Movies only hold the buttons of the controllers in the two ports
+fn movie_devices_plugged(nes: &Nes) -> bool {
+    nes.chipset.ports.iter().all(|device| device.name() == "controller") && nes.chipset.expansion.name() == "unplugged"
+}
+
+fn start_recording(app: &mut App, from_power_on: bool) {
+    if !movie_devices_plugged(&app.nes) {
+        println!("Movies can only be recorded with a controller in each port and nothing in the expansion port");
+        return;
+    }
+
+    if from_power_on {
+        let ram_fill = app.settings.ram_fill;
+        if let Err(e) = power_on(app, ram_fill) {
+            println!("Could not restart {}: {}", app.rom_path, e);
+            return;
+        }
+    }
+
+    let name = Path::new(&app.rom_path).file_stem().map(|s| s.to_string_lossy().into_owned()).unwrap_or_default();
+    let mut movie = Movie::new(&name, app.rom_checksum);
+    if from_power_on {
+        movie.ram_fill = Some(app.nes.ram_fill);
+    } else {
+        movie.savestate = Some(app.nes.save_state());
+    }
+
+    app.movie = Some(MoviePlayer::record(movie, app.nes.frame));
+    println!("Recording movie");
+}
+
+fn start_playback(app: &mut App, path: &str) {
+    let movie = match Movie::load(path) {
+        Ok(movie) => movie,
+        Err(e) => {
+            println!("Could not load movie from {}: {}", path, e);
+            return;
+        }
+    };
+
+    if movie.rom_checksum.map_or(false, |checksum| checksum != app.rom_checksum) {
+        println!("Movie {} was recorded with a different rom, it will probably desync", path);
     }
+
+    let started = match movie.savestate {
+        Some(ref state) => app.nes.load_state(state),
+        None => {
+            let ram_fill = movie.ram_fill.unwrap_or(app.settings.ram_fill);
+            power_on(app, ram_fill)
+        },
+    };
+    if let Err(e) = started {
+        println!("Could not start movie {}: {}", path, e);
+        return;
+    }
+
+    app.rewind.clear();
+    app.movie = Some(MoviePlayer::play(movie, app.nes.frame, true));
+    println!("Playing movie from {}", path);
 }
 
+fn stop_movie(app: &mut App) {
+    let path = movie_path(app);
+    if let Some(player) = app.movie.take() {
+        if !player.read_only {
+            match player.movie.save(&path) {
+                Ok(()) => println!("Saved movie to {}", path),
+                Err(e) => println!("Could not save movie to {}: {}", path, e),
+            }
+        }
+        println!("Movie stopped");
+    }
+}
+
+fn tape_path(app: &App) -> String {
+    format!("{}.wav", app.rom_path)
+}
+
+// Plays or records the tape, pressing either hotkey again stops it
+fn do_tape(app: &mut App, record: bool) {
+    let path = tape_path(app);
+    let keyboard = match app.nes.chipset.expansion_device::<FamilyKeyboard>() {
+        Some(keyboard) => keyboard,
+        None => {
+            println!("The data recorder needs the family basic keyboard");
+            return;
+        }
+    };
+    let recorder = &mut keyboard.recorder;
+
+    match recorder.mode {
+        TapeMode::Recording => {
+            recorder.stop();
+            match recorder.tape.save(&path) {
+                Ok(()) => println!("Saved tape to {}", path),
+                Err(e) => println!("Could not save tape to {}: {}", path, e),
+            }
+        },
+        TapeMode::Playing => {
+            recorder.stop();
+            println!("Tape stopped");
+        },
+        TapeMode::Stopped if record => {
+            recorder.record();
+            println!("Recording tape");
+        },
+        TapeMode::Stopped => match Tape::load(&path) {
+            Ok(tape) => {
+                recorder.play(tape);
+                println!("Playing tape from {}", path);
+            },
+            Err(e) => println!("Could not load tape from {}: {}", path, e),
+        },
+    }
+}
+
+// The Family BASIC keyboard by position on a US keyboard. Tab is ESC since escape closes the window
+fn family_key(key: Key) -> Option<&'static str> {
+    let name = match key {
+        Key::A => "A", Key::B => "B", Key::C => "C", Key::D => "D", Key::E => "E", Key::F => "F",
+        Key::G => "G", Key::H => "H", Key::I => "I", Key::J => "J", Key::K => "K", Key::L => "L",
+        Key::M => "M", Key::N => "N", Key::O => "O", Key::P => "P", Key::Q => "Q", Key::R => "R",
+        Key::S => "S", Key::T => "T", Key::U => "U", Key::V => "V", Key::W => "W", Key::X => "X",
+        Key::Y => "Y", Key::Z => "Z",
+        Key::D0 => "0", Key::D1 => "1", Key::D2 => "2", Key::D3 => "3", Key::D4 => "4",
+        Key::D5 => "5", Key::D6 => "6", Key::D7 => "7", Key::D8 => "8", Key::D9 => "9",
+        Key::F1 => "F1", Key::F2 => "F2", Key::F3 => "F3", Key::F4 => "F4",
+        Key::F5 => "F5", Key::F6 => "F6", Key::F7 => "F7", Key::F8 => "F8",
+        Key::Minus => "-", Key::Equals => "^", Key::Backslash => "YEN",
+        Key::LeftBracket => "@", Key::RightBracket => "[", Key::Backquote => "]",
+        Key::Semicolon => ";", Key::Quote => ":", Key::Comma => ",", Key::Period => ".", Key::Slash => "/",
+        Key::PageDown => "_", Key::End => "STOP", Key::Return => "RETURN", Key::Space => "SPACE",
+        Key::Tab => "ESC", Key::LCtrl => "CTR", Key::LShift => "LSHIFT", Key::RShift => "RSHIFT",
+        Key::LAlt => "GRPH", Key::RAlt => "KANA",
+        Key::Home => "CLR", Key::Insert => "INS", Key::Backspace => "DEL",
+        Key::Up => "UP", Key::Down => "DOWN", Key::Left => "LEFT", Key::Right => "RIGHT",
+        _ => return None
+    };
+    Some(name)
+}
+
+// The capture_keyboard hotkey hands the host keyboard over to a plugged in Family BASIC keyboard and back.
+// Returns true if the event was a key for the Family BASIC keyboard
+fn do_family_keyboard(app: &mut App, e: &Event) -> bool {
+    if let Some(Button::Keyboard(key)) = e.press_args() {
+        if app.settings.hotkey(key) == Some(Hotkey::CaptureKeyboard) {
+            if app.nes.chipset.expansion_device::<FamilyKeyboard>().is_some() {
+                app.keyboard_captured = !app.keyboard_captured;
+                println!("Keys go to the {}", if app.keyboard_captured { "family basic keyboard" } else { "emulator" });
+            }
+            return true;
+        }
+    }
+
+    if !app.keyboard_captured {
+        return false;
+    }
+
+    let keyboard = match app.nes.chipset.expansion_device::<FamilyKeyboard>() {
+        Some(keyboard) => keyboard,
+        None => {
+            app.keyboard_captured = false;
+            return false;
+        }
+    };
+
+    if let Some(Button::Keyboard(key)) = e.press_args() {
+        family_key(key).map(|name| keyboard.set_key(name, true));
+        return true;
+    }
+    if let Some(Button::Keyboard(key)) = e.release_args() {
+        family_key(key).map(|name| keyboard.set_key(name, false));
+        return true;
+    }
+    false
+}
 
+fn state_slot_key(key: Key) -> Option<u8> {
+    match key {
+        Key::D0 => Some(0),
+        Key::D1 => Some(1),
+        Key::D2 => Some(2),
+        Key::D3 => Some(3),
+        Key::D4 => Some(4),
+        Key::D5 => Some(5),
+        Key::D6 => Some(6),
+        Key::D7 => Some(7),
+        Key::D8 => Some(8),
+        Key::D9 => Some(9),
+        _ => None
+    }
+}
+
+// 0-9 pick a save state slot, the rest are set in the [hotkeys] settings
+fn do_hotkeys(app: &mut App, e: &Event) {
+    if let Some(Button::Keyboard(key)) = e.release_args() {
+        if app.settings.hotkey(key) == Some(Hotkey::Rewind) {
+            app.rewinding = false;
+            app.audio.muted = false;
+        }
+    }
+
+    if let Some(Button::Keyboard(key)) = e.press_args() {
+        if let Some(slot) = state_slot_key(key) {
+            app.state_slot = slot;
+            println!("Selected save state slot {}", slot);
+            return;
+        }
+
+        let hotkey = match app.settings.hotkey(key) {
+            Some(hotkey) => hotkey,
+            None => return
+        };
+
+        let path = format!("{}.state{}", app.rom_path, app.state_slot);
+        match hotkey {
+            Hotkey::Rewind => {
+                app.rewinding = true;
+                app.rewound = false;
+                app.audio.muted = true;
+            },
+            Hotkey::SaveState => match fs::write(&path, app.nes.save_state()) {
+                Ok(()) => println!("Saved state to {}", path),
+                Err(e) => println!("Could not save state to {}: {}", path, e),
+            },
+            Hotkey::LoadState => match fs::read(&path).and_then(|data| app.nes.load_state(&data)) {
+                Ok(()) => {
+                    app.rewind.clear();
+                    if let Some(ref mut player) = app.movie {
+                        player.state_loaded(app.nes.frame);
+                    }
+                    println!("Loaded state from {}", path)
+                },
+                Err(e) => println!("Could not load state from {}: {}", path, e),
+            },
+            Hotkey::SwapPort2 => swap_port_2(app),
+            Hotkey::FourScore => swap_four_score(app),
+            Hotkey::SwapExpansion => swap_expansion(app),
+            Hotkey::PlayTape => do_tape(app, false),
+            Hotkey::RecordTape => do_tape(app, true),
+            Hotkey::RecordMovie => start_recording(app, false),
+            Hotkey::RecordMovieFromPowerOn => start_recording(app, true),
+            Hotkey::StopMovie => stop_movie(app),
+            Hotkey::PlayMovie => {
+                let path = movie_path(app);
+                start_playback(app, &path);
+            },
+            Hotkey::MovieReadOnly => if let Some(ref mut player) = app.movie {
+                let read_only = !player.read_only;
+                player.set_read_only(read_only);
+                println!("Movie is {}", if read_only { "read-only" } else { "read+write" });
+            },
+            Hotkey::Reset => app.pending_commands |= COMMAND_SOFT_RESET,
+            Hotkey::PowerCycle => app.pending_commands |= COMMAND_POWER,
+            Hotkey::NextPalette => next_palette(app),
+            Hotkey::Trace => app.nes.cpu.debug = !app.nes.cpu.debug,
+            Hotkey::CaptureKeyboard => ()
+        }
+    }
+}
+
+fn print_info(rom_path: &str) -> std::io::Result<()> {
+    let (flags, prg, chr) = load_file(rom_path)?;
+    println!("{}", rom_path);
+    println!("Header: {:?}", flags);
+    println!("PRG ROM: {}KB, CHR ROM: {}KB", prg.len() / 1024, chr.len() / 1024);
+    println!("Mapper {} is {}", flags.mapper, if Nes::supports_mapper(flags.mapper) { "supported" } else { "not supported" });
+    let checksum: String = rom_checksum(&prg, &chr).iter().map(|b| format!("{:02x}", b)).collect();
+    println!("MD5 without header: {}", checksum);
+    Ok(())
+}
+
+// Runs a fixed number of frames with the movie's input, if any, and saves the last one
+fn run_headless(rom: (Flags, Vec<u8>, Vec<u8>), settings: &Settings, options: &cli::Options) -> Result<(), String> {
+    let movie = match options.movie {
+        Some(ref path) => Some(Movie::load(path).map_err(|e| format!("Could not load movie from {}: {}", path, e))?),
+        None => None,
+    };
+
+    let ram_fill = movie.as_ref().and_then(|movie| movie.ram_fill).unwrap_or(settings.ram_fill);
+    let mut nes = make_nes(rom, settings, ram_fill);
+    nes.cpu.debug = options.trace;
+
+    if let Some(ref path) = options.load_state {
+        let data = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        nes.load_state(&data).map_err(|e| format!("Could not load state from {}: {}", path, e))?;
+    }
+
+    let mut player = match movie {
+        Some(movie) => {
+            let path = options.movie.as_ref().unwrap();
+            if let Some(ref state) = movie.savestate {
+                nes.load_state(state).map_err(|e| format!("Could not start movie {}: {}", path, e))?;
+            }
+            Some(MoviePlayer::play(movie, nes.frame, true))
+        },
+        None => None,
+    };
+
+    for _ in 0..options.frames.unwrap_or(0) {
+        if let Some(ref mut player) = player {
+            let frame = player.next_frame(nes.frame, MovieFrame::default());
+            nes.chipset.set_buttons(0, frame.ports[0]);
+            nes.chipset.set_buttons(1, frame.ports[1]);
+            run_commands(&mut nes, frame.commands);
+        }
+        nes.tick_headless();
+    }
+
+    if let Some(ref path) = options.screenshot {
+        nes.chipset.ppu.output_canvas.save(path).map_err(|e| format!("Could not save {}: {}", path, e))?;
+        println!("Saved frame {} to {}", nes.frame, path);
+    }
+    Ok(())
+}
+
+fn main() {
+    let options = match cli::parse(&mut std::env::args().skip(1)) {
+        Ok(options) => options,
+        Err(e) => {
+            println!("{}\n\n{}", e, cli::USAGE);
+            std::process::exit(2);
+        }
+    };
+    if options.help {
+        println!("{}", cli::USAGE);
+        return;
+    }
+
+    let settings = match Settings::load(SETTINGS_PATH, &options.overrides) {
+        Ok(settings) => settings,
+        Err(e) => {
+            println!("Invalid settings: {}", e);
+            std::process::exit(1);
+        }
+    };
+
+    let rom_path = settings.rom.clone();
+    if options.info {
+        if let Err(e) = print_info(&rom_path) {
+            println!("Could not read {}: {}", rom_path, e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let rom = match load_file(&rom_path) {
+        Ok(rom) => rom,
+        Err(e) => {
+            println!("Could not load {}: {}", rom_path, e);
+            std::process::exit(1);
+        }
+    };
+
+    if options.headless {
+        if let Err(e) = run_headless(rom, &settings, &options) {
+            println!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    let input: Box<ControllerMethod> = Box::new(User {
+        dump_count: 0,
+        window_size: settings.window_size(),
+        keys: settings.keys.clone(),
+        power_pad_keys: settings.power_pad_keys.clone(),
+    });
+    emulate(&rom_path, rom, input, settings, &options);
+}
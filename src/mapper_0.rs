@@ -4,6 +4,9 @@ use std::fmt::Debug;
 use std::fmt::Error;
 use std::fmt::Formatter;
 use ppu::Ppu;
+use savestate::*;
+use power_on::*;
+use std::io;
 
 #[derive(Clone)]
 pub struct Mapper0 {
@@ -29,23 +32,21 @@ impl Mapper0 {
 }
 
 impl Mapper for Mapper0 {
-    fn read(&mut self, addr: u16) -> u8 {
+    fn read(&mut self, addr: u16) -> Option<u8> {
         assert!(self.prg_ram.len() == 8*1024, "PRG ram must be 8kB");
         assert!(self.prg.len() == 16*1024 || self.prg.len() == 32*1024, "PRG ram must be 16 or 32kb");
 
         match addr {
-            0x6000 ..= 0x7FFF => self.prg_ram[addr as usize - 0x6000],
-            0x8000 ..= 0xBFFF => self.prg[addr as usize - 0x8000],
+            0x6000 ..= 0x7FFF => Some(self.prg_ram[addr as usize - 0x6000]),
+            0x8000 ..= 0xBFFF => Some(self.prg[addr as usize - 0x8000]),
             0xC000 ..= 0xFFFF => {
                 if self.prg.len() == 32 * 1024 {
-                    self.prg[addr as usize - 0x8000]
+                    Some(self.prg[addr as usize - 0x8000])
                 } else {
-                    self.prg[mirror_addr(0x8000 ..= 0xBFFF, 0xC000 ..= 0xFFFF, addr) as usize - 0x8000]
+                    Some(self.prg[mirror_addr(0x8000 ..= 0xBFFF, 0xC000 ..= 0xFFFF, addr) as usize - 0x8000])
                 }
             },
-            _ => {
-                panic!("Reference to invalid mapper 0 address {:X}", addr);
-            }
+            _ => None
         }
     }
 
@@ -60,9 +61,7 @@ impl Mapper for Mapper0 {
                     self.prg[mirror_addr(0x8000 ..= 0xBFFF, 0xC000 ..= 0xFFFF, addr) as usize - 0x8000] = val
                 }
             },
-            _ => {
-                panic!("Reference to invalid mapper 0 address {:X}", addr);
-            }
+            _ => ()
         }
     }
 
@@ -89,4 +88,20 @@ impl Mapper for Mapper0 {
     }
 
     fn ppu_scanline(&mut self, _: &mut Cpu, _: &mut Ppu) -> bool { false }
-}
\ No newline at end of file
+
+    fn power_on(&mut self, fill: &RamFill) {
+        fill.fill(PRG_RAM, &mut self.prg_ram);
+    }
+}
+
+impl SaveState for Mapper0 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_vec(&self.prg_ram);
+        out.write_vec(&self.chr);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> io::Result<()> {
+        input.read_vec_into(&mut self.prg_ram)?;
+        input.read_vec_into(&mut self.chr)
+    }
+}
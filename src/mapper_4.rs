@@ -4,6 +4,9 @@ use std::fmt::Error;
 use std::fmt::Formatter;
 use std::fmt::Debug;
 use ppu::Ppu;
+use savestate::*;
+use power_on::*;
+use std::io;
 
 #[derive(Clone)]
 pub struct Mapper4 {
@@ -56,12 +59,17 @@ impl Mapper4 {
             dirty: false,
         }
     }
+
+    // Bank numbers past the end of the rom wrap around, like on the real chip
+    fn prg_bank(&self, bank: usize) -> usize {
+        (bank % (self.prg.len() / 0x2000)) * 0x2000
+    }
 }
 
 impl Mapper for Mapper4 {
-    fn read(&mut self, addr: u16) -> u8 {
+    fn read(&mut self, addr: u16) -> Option<u8> {
         match addr {
-            0x6000 ..= 0x7FFF => self.prg_ram[addr as usize - 0x6000],
+            0x6000 ..= 0x7FFF => Some(self.prg_ram[addr as usize - 0x6000]),
             0x8000 ..= 0x9FFF => {
                 let bank = if self.prg_rom_bank_mode {
                     self.prg.len() / 0x2000 - 2 // second-last bank
@@ -69,9 +77,9 @@ impl Mapper for Mapper4 {
                     self.registers[6] as usize & 0b0011_1111
                 };
 
-                self.prg[bank * 0x2000 + addr as usize - 0x8000]
+                Some(self.prg[self.prg_bank(bank) + addr as usize - 0x8000])
             },
-            0xA000 ..= 0xBFFF => self.prg[(self.registers[7] as usize & 0b00111111) * 0x2000 + addr as usize - 0xA000],
+            0xA000 ..= 0xBFFF => Some(self.prg[self.prg_bank(self.registers[7] as usize & 0b00111111) + addr as usize - 0xA000]),
             0xC000 ..= 0xDFFF => {
                 let bank = if !self.prg_rom_bank_mode {
                     self.prg.len() / 0x2000 - 2 // second-last bank
@@ -79,12 +87,10 @@ impl Mapper for Mapper4 {
                     self.registers[6] as usize & 0b0011_1111
                 };
 
-                self.prg[bank * 0x2000 + addr as usize - 0xC000]
+                Some(self.prg[self.prg_bank(bank) + addr as usize - 0xC000])
             },
-            0xE000 ..= 0xFFFF => self.prg[self.prg.len() - 0x2000 + addr as usize - 0xE000], // Last bank
-            _ => {
-                panic!("Read from invalid mapper 4 address {:X}", addr);
-            }
+            0xE000 ..= 0xFFFF => Some(self.prg[self.prg.len() - 0x2000 + addr as usize - 0xE000]), // Last bank
+            _ => None
         }
     }
 
@@ -110,9 +116,7 @@ impl Mapper for Mapper4 {
                 self.irq_reload = true;
             }
             0xE000 ..= 0xFFFF => self.irq_enable = addr%2 != 0,
-            _ => {
-                panic!("Write to invalid mapper 4 address {:X}", addr);
-            }
+            _ => ()
         }
         self.dirty = true;
     }
@@ -147,7 +151,7 @@ impl Mapper for Mapper4 {
                 } as usize;
                 let block = (addr as usize / 0x400) * 0x400;
 
-                self.chr[bank * 0x400 + addr as usize - block]
+                self.chr[(bank % (self.chr.len() / 0x400)) * 0x400 + addr as usize - block]
             }
             _ => {
                 panic!("Reference to invalid mapper 4 ppu address {:X}", addr);
@@ -182,4 +186,39 @@ impl Mapper for Mapper4 {
             false
         }
     }
-}
\ No newline at end of file
+
+    fn power_on(&mut self, fill: &RamFill) {
+        fill.fill(PRG_RAM, &mut self.prg_ram);
+    }
+}
+
+impl SaveState for Mapper4 {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_vec(&self.prg_ram);
+        out.write_bytes(&self.registers);
+        out.write_u8(self.register_to_update);
+        out.write_bool(self.prg_rom_bank_mode);
+        out.write_bool(self.chr_inversion);
+        out.write_bool(self.horizontal_mirroring);
+        out.write_u8(self.irq_counter);
+        out.write_u8(self.irq_counter_reload);
+        out.write_bool(self.irq_enable);
+        out.write_bool(self.irq_reload);
+        out.write_bool(self.dirty);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> io::Result<()> {
+        input.read_vec_into(&mut self.prg_ram)?;
+        input.read_bytes(&mut self.registers)?;
+        self.register_to_update = input.read_u8()?;
+        self.prg_rom_bank_mode = input.read_bool()?;
+        self.chr_inversion = input.read_bool()?;
+        self.horizontal_mirroring = input.read_bool()?;
+        self.irq_counter = input.read_u8()?;
+        self.irq_counter_reload = input.read_u8()?;
+        self.irq_enable = input.read_bool()?;
+        self.irq_reload = input.read_bool()?;
+        self.dirty = input.read_bool()?;
+        Ok(())
+    }
+}
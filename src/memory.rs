@@ -2,10 +2,14 @@ use std::ops::RangeInclusive;
 use cpu::Cpu;
 use objekt;
 use std::fmt::Debug;
+use std::io::Result;
 use ppu::Ppu;
+use savestate::*;
+use power_on::*;
 
-pub trait Mapper: objekt::Clone + Debug {
-    fn read(&mut self, addr: u16) -> u8;
+pub trait Mapper: objekt::Clone + Debug + SaveState {
+    // None where nothing on the cartridge drives the bus, which leaves the cpu reading open bus
+    fn read(&mut self, addr: u16) -> Option<u8>;
 
     fn write(&mut self, addr: u16, val: u8);
 
@@ -16,6 +20,14 @@ pub trait Mapper: objekt::Clone + Debug {
     fn horizontal_mirroring(&self, rom_val: bool) -> bool;
 
     fn ppu_scanline(&mut self, cpu: &mut Cpu, ppu: &mut Ppu) -> bool;
+
+    // For boards that react to the reset button, like multicarts that switch games on reset
+    fn reset(&mut self) {
+    }
+
+    // Fills the cartridge's ram, for boards that have some
+    fn power_on(&mut self, _fill: &RamFill) {
+    }
 }
 
 pub trait Mem {
@@ -35,12 +47,15 @@ pub trait Mem {
 
 pub struct Memory {
     pub ram: [u8; 2 * 1024],
+    // The last value on the cpu data bus, which is what reads from unmapped addresses see
+    pub open_bus: u8,
 }
 
 impl Memory {
     pub fn new() -> Memory {
         Memory {
-            ram: [0; 2048]
+            ram: [0; 2048],
+            open_bus: 0,
         }
     }
 }
@@ -50,10 +65,8 @@ impl Mem for Memory {
         match addr {
             0..=0x07FF => self.ram[addr as usize],
             0x0800..=0x1FFF => self.read(mapper, mirror_addr(0..=0x07FF, 0x0800..=0x1FFF, addr)),
-            0x4020..=0xFFFF => mapper.read(addr),
-            _ => {
-                panic!("Reference to invalid main address {:X}", addr);
-            }
+            0x4020..=0xFFFF => mapper.read(addr).unwrap_or(self.open_bus),
+            _ => self.open_bus
         }
     }
 
@@ -62,13 +75,24 @@ impl Mem for Memory {
             0..=0x07FF => self.ram[addr as usize] = val,
             0x0800..=0x1FFF => self.write(mapper, mirror_addr(0..=0x07FF, 0x0800..=0x1FFF, addr), val),
             0x4020..=0xFFFF => mapper.write(addr, val),
-            _ => {
-                panic!("Reference to invalid main address {:X}", addr);
-            }
+            _ => ()
         }
     }
 }
 
+impl SaveState for Memory {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_bytes(&self.ram);
+        out.write_u8(self.open_bus);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        input.read_bytes(&mut self.ram)?;
+        self.open_bus = input.read_u8()?;
+        Ok(())
+    }
+}
+
 pub fn mirror_addr(from : RangeInclusive<u16>, to : RangeInclusive<u16>, addr : u16) -> u16 {
     let size = from.end() - from.start() + 1;
 
@@ -0,0 +1,336 @@
+use md5::md5;
+use power_on::RamFill;
+use rand;
+use std::fs;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+
+// See http://www.fceux.com/web/help/fceux.html?fm2.html
+pub const COMMAND_SOFT_RESET: u8 = 1;
+pub const COMMAND_POWER: u8 = 2;
+
+// Button order of an fm2 input record, from the highest controller report bit to the lowest
+const FM2_BUTTONS: &[u8; 8] = b"RLDUTSBA";
+
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct MovieFrame {
+    pub commands: u8,
+    pub ports: [u8; 2],
+}
+
+// Like fceux, this is the md5 of the rom data without the ines header
+pub fn rom_checksum(prg: &[u8], chr: &[u8]) -> [u8; 16] {
+    let mut rom = prg.to_vec();
+    rom.extend_from_slice(chr);
+    md5(&rom)
+}
+
+#[derive(Debug, Clone)]
+pub struct Movie {
+    pub rom_filename: String,
+    pub rom_checksum: Option<[u8; 16]>,
+    pub guid: String,
+    pub rerecord_count: u32,
+    // Stored in this emulator's own format, so fceux movies that start from a state can't be played
+    pub savestate: Option<Vec<u8>>,
+    // Not part of fceux's format, movies from power on need the same ram contents to play back
+    pub ram_fill: Option<RamFill>,
+    pub comments: Vec<String>,
+    pub frames: Vec<MovieFrame>,
+}
+
+impl Movie {
+    pub fn new(rom_filename: &str, rom_checksum: [u8; 16]) -> Movie {
+        Movie {
+            rom_filename: rom_filename.to_string(),
+            rom_checksum: Some(rom_checksum),
+            guid: make_guid(),
+            rerecord_count: 0,
+            savestate: None,
+            ram_fill: None,
+            comments: vec![],
+            frames: vec![],
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Movie> {
+        let text = fs::read_to_string(path)?;
+        Movie::parse(&text)
+    }
+
+    pub fn save(&self, path: &str) -> Result<()> {
+        fs::write(path, self.to_fm2())
+    }
+
+    pub fn parse(text: &str) -> Result<Movie> {
+        let mut movie = Movie {
+            rom_filename: String::new(),
+            rom_checksum: None,
+            guid: String::new(),
+            rerecord_count: 0,
+            savestate: None,
+            ram_fill: None,
+            comments: vec![],
+            frames: vec![],
+        };
+
+        for line in text.lines() {
+            let line = line.trim_right_matches('\r');
+
+            if line.starts_with('|') {
+                movie.frames.push(parse_frame(line)?);
+                continue;
+            }
+
+            let mut parts = line.splitn(2, ' ');
+            let key = parts.next().unwrap_or("");
+            let val = parts.next().unwrap_or("").trim();
+
+            match key {
+                "version" if val != "3" => return Err(invalid(format!("Unsupported fm2 version {}", val))),
+                "binary" if val != "0" => return Err(invalid("Binary fm2 movies are not supported")),
+                "fourscore" if val != "0" => return Err(invalid("Four score movies are not supported")),
+                "port0" | "port1" if val != "0" && val != "1" => {
+                    return Err(invalid(format!("Unsupported input device {} on {}", val, key)));
+                },
+                "palFlag" if val != "0" => return Err(invalid("PAL movies are not supported")),
+                "romFilename" => movie.rom_filename = val.to_string(),
+                "romChecksum" => {
+                    let checksum = decode_binary(val)?;
+                    if checksum.len() != 16 {
+                        return Err(invalid("romChecksum is not an md5 sum"));
+                    }
+
+                    let mut md5 = [0; 16];
+                    md5.copy_from_slice(&checksum);
+                    movie.rom_checksum = Some(md5);
+                },
+                "guid" => movie.guid = val.to_string(),
+                "rerecordCount" => {
+                    movie.rerecord_count = val.parse().map_err(|_| invalid("Invalid rerecordCount"))?;
+                },
+                "savestate" => movie.savestate = Some(decode_binary(val)?),
+                "ramFill" => {
+                    movie.ram_fill = Some(RamFill::parse(val).ok_or_else(|| invalid(format!("Unknown ramFill {}", val)))?);
+                },
+                "comment" => movie.comments.push(val.to_string()),
+                _ => ()
+            }
+        }
+
+        Ok(movie)
+    }
+
+    pub fn to_fm2(&self) -> String {
+        let mut out = String::new();
+        out += "version 3\n";
+        out += "emuVersion 22020\n";
+        out += &format!("rerecordCount {}\n", self.rerecord_count);
+        out += "palFlag 0\n";
+        out += &format!("romFilename {}\n", self.rom_filename);
+        if let Some(ref checksum) = self.rom_checksum {
+            out += &format!("romChecksum base64:{}\n", encode_base64(checksum));
+        }
+        out += &format!("guid {}\n", self.guid);
+        out += "fourscore 0\nmicrophone 0\nport0 1\nport1 1\nport2 0\nFDS 0\nNewPPU 0\n";
+        for comment in &self.comments {
+            out += &format!("comment {}\n", comment);
+        }
+        if let Some(ram_fill) = self.ram_fill {
+            out += &format!("ramFill {}\n", ram_fill);
+        }
+        if let Some(ref state) = self.savestate {
+            out += &format!("savestate base64:{}\n", encode_base64(state));
+        }
+
+        for frame in &self.frames {
+            out += &format!("|{}|{}|{}||\n", frame.commands,
+                            format_buttons(frame.ports[0]), format_buttons(frame.ports[1]));
+        }
+
+        out
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum MovieMode {
+    Recording,
+    Playing,
+    Finished,
+}
+
+// Drives the controllers from a movie, frames are counted from the console frame the movie started on
+pub struct MoviePlayer {
+    pub movie: Movie,
+    pub mode: MovieMode,
+    pub read_only: bool,
+    start_frame: u64,
+}
+
+impl MoviePlayer {
+    pub fn record(movie: Movie, nes_frame: u64) -> MoviePlayer {
+        MoviePlayer {
+            movie: movie,
+            mode: MovieMode::Recording,
+            read_only: false,
+            start_frame: nes_frame,
+        }
+    }
+
+    pub fn play(movie: Movie, nes_frame: u64, read_only: bool) -> MoviePlayer {
+        MoviePlayer {
+            movie: movie,
+            mode: MovieMode::Playing,
+            read_only: read_only,
+            start_frame: nes_frame,
+        }
+    }
+
+    pub fn frame(&self, nes_frame: u64) -> u64 {
+        nes_frame.saturating_sub(self.start_frame)
+    }
+
+    // Call before each frame with the input from the user, and run the frame with the returned input
+    pub fn next_frame(&mut self, nes_frame: u64, live: MovieFrame) -> MovieFrame {
+        let index = self.frame(nes_frame) as usize;
+
+        match self.mode {
+            MovieMode::Recording => {
+                self.movie.frames.truncate(index);
+                self.movie.frames.push(live);
+                live
+            },
+            MovieMode::Playing => match self.movie.frames.get(index) {
+                Some(&frame) => frame,
+                None => {
+                    self.mode = MovieMode::Finished;
+                    live
+                }
+            },
+            MovieMode::Finished => live,
+        }
+    }
+
+    // Loading a state in read+write mode is a rerecord: the movie is cut at the loaded frame and recording continues
+    pub fn state_loaded(&mut self, nes_frame: u64) {
+        self.rewound(nes_frame);
+        if !self.read_only {
+            self.movie.rerecord_count += 1;
+        }
+    }
+
+    // Each step of a rewind restores a state, but holding rewind only counts as one rerecord
+    pub fn rewound(&mut self, nes_frame: u64) {
+        let index = self.frame(nes_frame) as usize;
+
+        if self.read_only {
+            self.mode = if index < self.movie.frames.len() { MovieMode::Playing } else { MovieMode::Finished };
+        } else {
+            self.mode = MovieMode::Recording;
+            self.movie.frames.truncate(index);
+        }
+    }
+
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+        if read_only && self.mode == MovieMode::Recording {
+            self.mode = MovieMode::Playing;
+        }
+    }
+}
+
+fn invalid<E: Into<Box<::std::error::Error + Send + Sync>>>(error: E) -> Error {
+    Error::new(ErrorKind::InvalidData, error)
+}
+
+fn parse_frame(line: &str) -> Result<MovieFrame> {
+    let fields: Vec<&str> = line.split('|').collect();
+    if fields.len() < 3 {
+        return Err(invalid(format!("Invalid input record {}", line)));
+    }
+
+    let commands = fields[1].trim().parse().map_err(|_| invalid(format!("Invalid input record {}", line)))?;
+    let port = |i: usize| fields.get(i).map(|buttons| parse_buttons(buttons)).unwrap_or(0);
+
+    Ok(MovieFrame {
+        commands: commands,
+        ports: [port(2), port(3)],
+    })
+}
+
+fn parse_buttons(buttons: &str) -> u8 {
+    let mut val = 0;
+    for (i, c) in buttons.bytes().take(8).enumerate() {
+        if c != b'.' && c != b' ' {
+            val |= 0b1000_0000 >> i;
+        }
+    }
+    val
+}
+
+fn format_buttons(buttons: u8) -> String {
+    (0..8).map(|i| if buttons & (0b1000_0000 >> i) != 0 { FM2_BUTTONS[i] as char } else { '.' }).collect()
+}
+
+fn make_guid() -> String {
+    let hi = rand::random::<u64>();
+    let lo = rand::random::<u64>();
+    format!("{:08X}-{:04X}-{:04X}-{:04X}-{:012X}", hi >> 32, (hi >> 16) & 0xFFFF, hi & 0xFFFF,
+            lo >> 48, lo & 0xFFFF_FFFF_FFFF)
+}
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode_base64(data: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let b = [chunk[0], *chunk.get(1).unwrap_or(&0), *chunk.get(2).unwrap_or(&0)];
+        let n = ((b[0] as u32) << 16) | ((b[1] as u32) << 8) | b[2] as u32;
+
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[((n >> (18 - 6*i)) & 0x3F) as usize] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+// fm2 binary fields are either base64:... or 0x... hex
+fn decode_binary(val: &str) -> Result<Vec<u8>> {
+    if val.starts_with("base64:") {
+        let mut out = vec![];
+        let mut n: u32 = 0;
+        let mut bits = 0;
+
+        for c in val["base64:".len()..].bytes() {
+            if c == b'=' { break; }
+            let digit = match BASE64.iter().position(|&d| d == c) {
+                Some(digit) => digit as u32,
+                None => return Err(invalid("Invalid base64 data")),
+            };
+
+            n = (n << 6) | digit;
+            bits += 6;
+            if bits >= 8 {
+                bits -= 8;
+                out.push((n >> bits) as u8);
+                n &= (1 << bits) - 1;
+            }
+        }
+        Ok(out)
+    } else if val.starts_with("0x") {
+        let hex = &val[2..];
+        if hex.len() % 2 != 0 {
+            return Err(invalid("Hex data has an odd number of digits"));
+        }
+        (0..hex.len() / 2)
+            .map(|i| u8::from_str_radix(&hex[2*i..2*i + 2], 16).map_err(|_| invalid("Invalid hex data")))
+            .collect()
+    } else {
+        Err(invalid(format!("Unsupported binary field {}", val)))
+    }
+}
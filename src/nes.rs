@@ -1,16 +1,33 @@
 use cpu::*;
 use memory::*;
 use controller::*;
+use input::*;
 use ppu::*;
 use std::io;
+use std::mem;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
 use mapper_0::*;
 use mapper_4::*;
 use sound::*;
-use sdl2::Sdl;
+use objekt;
+use savestate::*;
+use dma::*;
+use power_on::*;
 
 pub struct Nes {
     pub cpu: Cpu,
     pub chipset: Chipset,
+    // Frames emulated since power on, movies use it to line their input up with the machine
+    pub frame: u64,
+    // Used again when power cycling, so a seeded run stays repeatable
+    pub ram_fill: RamFill,
+
+    rom_hash: u32,
+    // What the cartridge was like before anything ran, for power cycling
+    power_on_mapper: Box<Mapper>,
+    horiz_mapping: bool,
 }
 
 pub struct Chipset {
@@ -18,11 +35,12 @@ pub struct Chipset {
     pub mem: Memory,
     pub ppu: Ppu,
     pub sound: NesSound,
-    pub controller1: Controller,
-    pub controller2: Controller,
+    pub ports: [Box<InputDevice>; 2],
+    pub expansion: Box<InputDevice>,
 
-    ppu_dma_requested: bool,
-    ppu_dma_val: u8,
+    pub dma: Dma,
+    // For the dma unit, which makes the halted cpu repeat its last read
+    pub last_read: u16,
 
     ppu_writes_requested: Vec<(u16, u8)>,
 }
@@ -38,43 +56,67 @@ fn get_line() -> String {
 }
 
 impl Nes {
-    pub fn new(prg: Vec<u8>, mut chr: Vec<u8>, mapper: u8, prg_ram_size: usize,
-               horiz_mapping: bool, sdl: Sdl) -> Nes {
+    pub fn new(prg: Vec<u8>, chr: Vec<u8>, mapper: u8, prg_ram_size: usize,
+               horiz_mapping: bool) -> Nes {
+        Nes::with_ram_fill(prg, chr, mapper, prg_ram_size, horiz_mapping, RamFill::Zeros)
+    }
+
+    pub fn with_ram_fill(prg: Vec<u8>, mut chr: Vec<u8>, mapper: u8, prg_ram_size: usize,
+                         horiz_mapping: bool, ram_fill: RamFill) -> Nes {
         if chr.len() == 0 {
             chr = vec![0; 8*1024];
         }
 
+        let rom_hash = hash_rom(&prg, &chr);
         let mut mem = Memory::new();
         let mut mapper = match mapper {
             0 => Box::new(Mapper0::new(prg, prg_ram_size, chr)) as Box<Mapper>,
             4 => Box::new(Mapper4::new(prg, prg_ram_size, chr)) as Box<Mapper>,
             _ => panic!("Mapper: {}", mapper)
         };
+        mapper.power_on(&ram_fill);
+        ram_fill.fill(CPU_RAM, &mut mem.ram);
+        let mut ppu = Ppu::new(horiz_mapping);
+        ppu.power_on(&ram_fill);
+        let power_on_mapper = objekt::clone_box(&*mapper);
 
         Nes {
             cpu: Cpu::new(mem.read16(&mut mapper, 0xFFFC)),
             chipset: Chipset {
                 mapper: mapper,
                 mem: mem,
-                ppu: Ppu::new(horiz_mapping),
-                sound: init_audio(sdl),
-                ppu_dma_requested: false,
-                ppu_dma_val: 0,
-                controller1: Controller::new(),
-                controller2: Controller::new(),
+                ppu: ppu,
+                sound: NesSound::new(),
+                dma: Dma::new(),
+                last_read: 0,
+                ports: [Box::new(Controller::new()), Box::new(Controller::new())],
+                expansion: Box::new(Unplugged),
 
                 ppu_writes_requested: vec![],
             },
+            frame: 0,
+            ram_fill: ram_fill,
+            rom_hash: rom_hash,
+            power_on_mapper: power_on_mapper,
+            horiz_mapping: horiz_mapping,
+        }
+    }
+
+    pub fn supports_mapper(mapper: u8) -> bool {
+        match mapper {
+            0 | 4 => true,
+            _ => false
         }
     }
 
     pub fn tick(&mut self) {
         let frame_time = 1789773/60;
+        self.chipset.dma.frame_parity = (self.frame % 2) as u32;
+        self.chipset.sound.start_frame();
         while self.cpu.count < frame_time {
-            if self.chipset.ppu_dma_requested {
-                self.chipset.ppu_dma_requested = false;
-                self.chipset.ppu.ppudma(&mut self.chipset.mapper, self.chipset.ppu_dma_val,
-                                        &mut self.cpu, &mut self.chipset.mem);
+            let count = self.cpu.count;
+            if let Some(page) = self.chipset.dma.oam_page.take() {
+                oam_dma(&mut self.chipset, &mut self.cpu, page);
             }
 
             if self.chipset.ppu_writes_requested.len() > 0 {
@@ -85,8 +127,11 @@ impl Nes {
             }
 
             self.cpu.tick(&mut self.chipset);
-            self.chipset.ppu.tick(&mut self.cpu, &mut self.chipset.mapper);
             self.chipset.sound.tick(&mut self.cpu, &mut self.chipset.mapper);
+            let instruction_end = self.cpu.count;
+            dmc_dma(&mut self.chipset, &mut self.cpu, instruction_end);
+            self.chipset.ppu.tick(&mut self.cpu, &mut self.chipset.mapper);
+            self.chipset.tick_devices(self.cpu.count - count);
 
 //            if self.cpu.debug {
 //                if get_line().starts_with("d") {
@@ -96,6 +141,106 @@ impl Nes {
         }
 
         self.cpu.count -= frame_time;
+        self.chipset.sound.end_frame(frame_time);
+        self.frame += 1;
+    }
+
+    // Like pressing the reset button: ram and vram keep their contents and the cpu starts over
+    // from the reset vector
+    pub fn reset(&mut self) {
+        let chipset = &mut self.chipset;
+        chipset.mapper.reset();
+        chipset.ppu.reset(&self.cpu, &chipset.mapper);
+        chipset.sound.reset();
+        chipset.dma.oam_page = None;
+        chipset.ppu_writes_requested.clear();
+
+        let pc = chipset.mem.read16(&mut chipset.mapper, 0xFFFC);
+        self.cpu.reset(pc);
+    }
+
+    // Like switching the console off and on. The input devices stay plugged in, and the frame
+    // count carries on so that movies stay lined up with the machine
+    pub fn power_cycle(&mut self) {
+        let palette = self.chipset.ppu.palette.clone();
+        let sprite_limit = self.chipset.ppu.sprite_limit;
+        let count = self.cpu.count;
+        let debug = self.cpu.debug;
+
+        let chipset = &mut self.chipset;
+        chipset.mapper = objekt::clone_box(&*self.power_on_mapper);
+        chipset.mem = Memory::new();
+        self.ram_fill.fill(CPU_RAM, &mut chipset.mem.ram);
+        chipset.ppu = Ppu::new(self.horiz_mapping);
+        chipset.ppu.power_on(&self.ram_fill);
+        chipset.ppu.palette = palette;
+        chipset.ppu.sprite_limit = sprite_limit;
+        chipset.sound.power_cycle();
+        chipset.dma.oam_page = None;
+        chipset.ppu_writes_requested.clear();
+
+        self.cpu = Cpu::new(chipset.mem.read16(&mut chipset.mapper, 0xFFFC));
+        self.cpu.count = count;
+        self.cpu.debug = debug;
+    }
+
+    // Runs a frame and renders it to the ppu output canvas, for use without a window
+    pub fn tick_headless(&mut self) {
+        self.tick();
+        self.chipset.ppu.prepare_draw(&mut self.chipset.mapper);
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = StateWriter::new();
+        out.write_bytes(&STATE_MAGIC);
+        out.write_u32(STATE_VERSION);
+        out.write_u32(self.rom_hash);
+        out.write_u64(self.frame);
+
+        self.cpu.save_state(&mut out);
+        self.chipset.save_state(&mut out);
+        out.into_bytes()
+    }
+
+    // If the state can't be loaded, the machine is left as it was
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        let mut input = StateReader::new(data);
+
+        let mut magic = [0; 4];
+        input.read_bytes(&mut magic)?;
+        if magic != STATE_MAGIC {
+            return Err(Error::new(ErrorKind::InvalidData, "Not a save state"));
+        }
+
+        let version = input.read_u32()?;
+        if version != STATE_VERSION {
+            return Err(Error::new(ErrorKind::InvalidData,
+                                  format!("Save state version {} is not supported", version)));
+        }
+
+        if input.read_u32()? != self.rom_hash {
+            return Err(Error::new(ErrorKind::InvalidData, "Save state is for a different rom"));
+        }
+
+        let backup = self.save_state();
+        match self.load_components(&mut input) {
+            Ok(()) => Ok(()),
+            Err(e) => {
+                self.load_state(&backup).expect("Could not restore the state before loading");
+                Err(e)
+            }
+        }
+    }
+
+    fn load_components(&mut self, input: &mut StateReader) -> Result<()> {
+        self.frame = input.read_u64()?;
+        self.cpu.load_state(input)?;
+        self.chipset.load_state(input)?;
+
+        if !input.is_empty() {
+            return Err(Error::new(ErrorKind::InvalidData, "Save state has trailing data"));
+        }
+        Ok(())
     }
 
     pub fn prepare_draw(&mut self, canvas: &mut NesImageBuffer) {
@@ -113,36 +258,90 @@ impl Nes {
 }
 
 impl Chipset {
+    // Every read and write leaves its value on the data bus, which is what unmapped addresses return
     pub fn read(&mut self, addr: u16) -> u8 {
-        match addr as usize {
+        self.last_read = addr;
+        let val = match addr as usize {
             0x2000 ..= 0x2007 => self.ppu.read_main(&mut self.mapper, addr),
             0x2008..=0x3FFF => self.read(mirror_addr(0x2000..=0x2007, 0x2008..=0x3FFF, addr)),
-            0x4014 => self.ppu.read_main(&mut self.mapper, addr),
-            0x4016 => self.controller1.read(&mut self.mapper, addr),
-            0x4000 ..= 0x4017 => self.sound.read(&mut self.mapper, addr),
+            0x4015 => self.sound.read(&mut self.mapper, addr) | (self.mem.open_bus & 0b00100000),
+            // Only the low bits are driven by the ports, the rest keep what was on the bus, usually the $40
+            // from the address
+            0x4016 | 0x4017 => {
+                let port = self.ports[addr as usize - 0x4016].read(addr, &mut self.ppu, &mut self.mapper);
+                (self.mem.open_bus & 0b11100000) | port | self.expansion.read(addr, &mut self.ppu, &mut self.mapper)
+            },
+            // The apu registers besides $4015 and $4014 are write-only, and $4018-$401F are disabled test registers
+            0x4000 ..= 0x401F => self.mem.open_bus,
             _ => self.mem.read(&mut self.mapper, addr)
-        }
+        };
+        self.mem.open_bus = val;
+        val
     }
 
     pub fn write(&mut self, addr: u16, val: u8) {
+        self.mem.open_bus = val;
         match addr as usize {
             0x2000 ..= 0x2007 => {
                 self.ppu_writes_requested.push((addr, val));
             },
             0x2008..=0x3FFF => self.write(mirror_addr(0x2000..=0x2007, 0x2008..=0x3FFF, addr), val),
-            0x4014 => {
-                self.ppu_dma_requested = true;
-                self.ppu_dma_val = val;
-            },
+            0x4014 => self.dma.oam_page = Some(val),
             0x4016 => {
-                self.controller1.write(&mut self.mapper, addr, val);
-                self.controller2.write(&mut self.mapper, addr, val);
+                self.ports[0].write(val);
+                self.ports[1].write(val);
+                self.expansion.write(val);
             },
             0x4000 ..= 0x4017 => self.sound.write(&mut self.mapper, addr, val),
             _ => self.mem.write(&mut self.mapper, addr, val)
         }
     }
 
+    fn tick_devices(&mut self, cycles: u32) {
+        self.ports[0].tick(cycles);
+        self.ports[1].tick(cycles);
+        self.expansion.tick(cycles);
+    }
+
+    // Replaces the device in port 0 or 1, returning the one that was unplugged
+    pub fn plug(&mut self, port: usize, device: Box<InputDevice>) -> Box<InputDevice> {
+        mem::replace(&mut self.ports[port], device)
+    }
+
+    pub fn plug_expansion(&mut self, device: Box<InputDevice>) -> Box<InputDevice> {
+        mem::replace(&mut self.expansion, device)
+    }
+
+    pub fn device<T: InputDevice + 'static>(&mut self, port: usize) -> Option<&mut T> {
+        self.ports[port].as_any_mut().downcast_mut::<T>()
+    }
+
+    pub fn expansion_device<T: InputDevice + 'static>(&mut self) -> Option<&mut T> {
+        self.expansion.as_any_mut().downcast_mut::<T>()
+    }
+
+    // Players 1 and 2 are the ports. Players 3 and 4 are on a four score, or failing that the expansion port
+    pub fn controller(&mut self, player: usize) -> Option<&mut Controller> {
+        match player {
+            0 | 1 => self.ports[player].controller(0),
+            _ => match self.ports[player - 2].controller(1) {
+                Some(controller) => Some(controller),
+                None => self.expansion.controller(player - 2),
+            }
+        }
+    }
+
+    // Does nothing if the player has no standard controller
+    pub fn set_buttons(&mut self, player: usize, buttons: u8) {
+        if let Some(controller) = self.controller(player) {
+            controller.set_buttons(buttons);
+        }
+    }
+
+    pub fn buttons(&mut self, player: usize) -> u8 {
+        self.controller(player).map_or(0, |controller| controller.buttons())
+    }
+
     pub fn read16(&mut self, addr: u16) -> u16 {
         self.read(addr) as u16 + ((self.read(addr+1) as u16)<<8)
     }
@@ -151,4 +350,52 @@ impl Chipset {
         self.write(addr, (val&0x00FF) as u8);
         self.write(addr+1, ((val&0xFF00)>>8) as u8);
     }
-}
\ No newline at end of file
+}
+
+impl SaveState for Chipset {
+    fn save_state(&self, out: &mut StateWriter) {
+        self.mapper.save_state(out);
+        self.mem.save_state(out);
+        self.ppu.save_state(out);
+        self.sound.save_state(out);
+        save_device(&*self.ports[0], out);
+        save_device(&*self.ports[1], out);
+        save_device(&*self.expansion, out);
+
+        self.dma.save_state(out);
+        out.write_u32(self.ppu_writes_requested.len() as u32);
+        for &(addr, val) in &self.ppu_writes_requested {
+            out.write_u16(addr);
+            out.write_u8(val);
+        }
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        self.mapper.load_state(input)?;
+        self.mem.load_state(input)?;
+        self.ppu.load_state(input)?;
+        self.sound.load_state(input)?;
+        load_device(&mut *self.ports[0], input)?;
+        load_device(&mut *self.ports[1], input)?;
+        load_device(&mut *self.expansion, input)?;
+
+        self.dma.load_state(input)?;
+        self.ppu_writes_requested.clear();
+        for _ in 0..input.read_u32()? {
+            let addr = input.read_u16()?;
+            let val = input.read_u8()?;
+            self.ppu_writes_requested.push((addr, val));
+        }
+        Ok(())
+    }
+}
+
+// FNV-1a, used to check that a save state belongs to the loaded rom
+fn hash_rom(prg: &[u8], chr: &[u8]) -> u32 {
+    let mut hash: u32 = 0x811C_9DC5;
+    for &b in prg.iter().chain(chr.iter()) {
+        hash ^= b as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+    }
+    hash
+}
@@ -0,0 +1,258 @@
+use std::f32::consts::PI;
+use std::fs;
+use std::io::{Error, ErrorKind, Result};
+
+// RGB for each of the 64 colours, under each of the 8 combinations of the emphasis bits
+#[derive(Clone)]
+pub struct Palette {
+    rgb: Vec<[u8; 3]>,
+}
+
+// The palettes that can be picked by name
+pub const BUILT_IN: [&str; 3] = ["classic", "ntsc", "rgb"];
+
+// How much an emphasis bit darkens the colours it doesn't emphasise
+const EMPHASIS_ATTENUATION: f32 = 0.746;
+
+impl Palette {
+    // A .pal file is 64 RGB colours, or 512 with the colours for each combination of emphasis bits
+    pub fn from_bytes(data: &[u8]) -> Result<Palette> {
+        match data.len() {
+            192 => Ok(Palette::with_emphasis(data)),
+            1536 => Ok(Palette { rgb: data.chunks(3).map(|c| [c[0], c[1], c[2]]).collect() }),
+            _ => Err(Error::new(ErrorKind::InvalidData, "A palette needs 64 or 512 colours"))
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Palette> {
+        Palette::from_bytes(&fs::read(path)?)
+    }
+
+    // ntsc is generated with the default settings
+    pub fn built_in(name: &str) -> Option<Palette> {
+        match name {
+            "classic" => Some(Palette::with_emphasis(&CLASSIC)),
+            "ntsc" => Some(Palette::ntsc(&NtscSettings::default())),
+            "rgb" => Some(Palette::rgb_ppu()),
+            _ => None
+        }
+    }
+
+    // Emphasis is bits 5-7 of PPUMASK, red, green and blue in bits 0-2
+    pub fn colour(&self, colour: u8, emphasis: u8) -> [u8; 3] {
+        self.rgb(((emphasis & 7) as u16) << 6 | (colour & 0x3F) as u16)
+    }
+
+    // A 9 bit index, the colour with the emphasis bits above it
+    pub fn rgb(&self, idx: u16) -> [u8; 3] {
+        self.rgb[(idx & 0x1FF) as usize]
+    }
+
+    // Each emphasis bit darkens the other two colours, but the blacks in columns $xE and $xF stay black
+    // See https://wiki.nesdev.com/w/index.php/NTSC_video#Color_Tint_Bits
+    fn with_emphasis(base: &[u8]) -> Palette {
+        let mut rgb = vec![];
+        for emphasis in 0..8 {
+            for colour in 0..64 {
+                let mut entry = [0; 3];
+                for channel in 0..3 {
+                    entry[channel] = emphasised(base, colour, emphasis, channel);
+                }
+                rgb.push(entry);
+            }
+        }
+        Palette { rgb: rgb }
+    }
+
+    // The 2C03 and 2C05 in the arcade machines and the Famicom Titler output RGB directly, 3 bits
+    // a channel. Their emphasis bits turn a channel all the way up instead
+    // See https://wiki.nesdev.com/w/index.php/PPU_palettes#2C03_and_2C05
+    fn rgb_ppu() -> Palette {
+        let mut rgb = vec![];
+        for emphasis in 0..8 {
+            for &val in RGB_PPU.iter() {
+                let mut entry = [0; 3];
+                for channel in 0..3 {
+                    let level = if emphasis & (1<<channel) != 0 { 7 } else { (val >> (6 - 3*channel)) & 7 };
+                    entry[channel] = (level * 255 / 7) as u8;
+                }
+                rgb.push(entry);
+            }
+        }
+        Palette { rgb: rgb }
+    }
+
+    pub fn ntsc(settings: &NtscSettings) -> Palette {
+        Palette { rgb: (0..512).map(|i| ntsc_colour(i, settings)).collect() }
+    }
+}
+
+fn emphasised(base: &[u8], colour: usize, emphasis: usize, channel: usize) -> u8 {
+    let mut val = base[colour * 3 + channel] as f32;
+    if colour & 0x0F < 0x0E {
+        for bit in 0..3 {
+            if emphasis & (1<<bit) != 0 && bit != channel {
+                val *= EMPHASIS_ATTENUATION;
+            }
+        }
+    }
+    val as u8
+}
+
+// Brightness of a 9 bit index as the classic palette shows it, whichever palette is on screen.
+// This is what the zapper's light sensor sees, so hit detection doesn't change with the palette
+pub fn luma(idx: u16) -> u8 {
+    let colour = (idx & 0x3F) as usize;
+    let emphasis = ((idx >> 6) & 7) as usize;
+    let weights = [299, 587, 114];
+    let sum: u32 = (0..3).map(|channel| emphasised(&CLASSIC, colour, emphasis, channel) as u32 * weights[channel]).sum();
+    (sum/1000) as u8
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette::with_emphasis(&CLASSIC)
+    }
+}
+
+// Adjustments to the generated ntsc palette, like the knobs on a tv
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct NtscSettings {
+    // Degrees
+    pub hue: f32,
+    pub saturation: f32,
+    pub contrast: f32,
+    pub brightness: f32,
+    // The tv is taken to be 2.2, lower values darken the mid tones
+    pub gamma: f32,
+}
+
+impl Default for NtscSettings {
+    fn default() -> NtscSettings {
+        NtscSettings {
+            hue: 0.0,
+            saturation: 1.0,
+            contrast: 1.0,
+            brightness: 1.0,
+            gamma: 1.8,
+        }
+    }
+}
+
+// Signal levels for the 4 rows of colours when the wave is low, then when it is high
+const NTSC_LEVELS: [f32; 8] = [0.350, 0.518, 0.962, 1.550, 1.094, 1.506, 1.962, 1.962];
+const NTSC_BLACK: f32 = 0.518;
+const NTSC_WHITE: f32 = 1.962;
+
+// The ppu makes colours as a square wave, high for 6 of the 12 phases of the colour subcarrier. This
+// builds the wave for one of the 512 colours and decodes it the way a tv would
+// See https://wiki.nesdev.com/w/index.php/NTSC_video
+fn ntsc_colour(idx: usize, settings: &NtscSettings) -> [u8; 3] {
+    let colour = idx & 0x0F;
+    let level = if colour > 0x0D { 1 } else { (idx >> 4) & 3 };
+    let emphasis = idx >> 6;
+
+    let low = NTSC_LEVELS[level + if colour == 0x00 { 4 } else { 0 }];
+    let high = NTSC_LEVELS[level + if colour < 0x0D { 4 } else { 0 }];
+    let in_phase = |phase: usize, colour: usize| (colour + phase + 8) % 12 < 6;
+
+    let (mut y, mut i, mut q) = (0.0, 0.0, 0.0);
+    for phase in 0..12 {
+        let mut signal = if in_phase(phase, colour) { high } else { low };
+        // Emphasis lowers the signal during the phases of the other colours
+        if (emphasis & 1 != 0 && in_phase(phase, 12))
+                || (emphasis & 2 != 0 && in_phase(phase, 4))
+                || (emphasis & 4 != 0 && in_phase(phase, 8)) {
+            signal *= EMPHASIS_ATTENUATION;
+        }
+
+        let val = (signal - NTSC_BLACK) / (NTSC_WHITE - NTSC_BLACK);
+        let val = ((val - 0.5) * settings.contrast + 0.5) * settings.brightness / 12.0;
+        let angle = PI / 6.0 * phase as f32 + settings.hue.to_radians();
+        y += val;
+        i += val * angle.cos();
+        q += val * angle.sin();
+    }
+    i *= settings.saturation;
+    q *= settings.saturation;
+
+    let gamma = |val: f32| if val <= 0.0 { 0 } else { (val.powf(2.2 / settings.gamma) * 255.0).min(255.0) as u8 };
+    [gamma(y + 0.946882*i + 0.623557*q),
+     gamma(y - 0.274788*i - 0.635691*q),
+     gamma(y - 1.108545*i + 1.709007*q)]
+}
+
+// Red, green and blue as octal digits
+static RGB_PPU: [u32; 64] = [
+    0o333, 0o014, 0o006, 0o326, 0o403, 0o503, 0o510, 0o420, 0o320, 0o120, 0o031, 0o040, 0o022, 0o000, 0o000, 0o000,
+    0o555, 0o036, 0o027, 0o407, 0o507, 0o704, 0o700, 0o630, 0o430, 0o140, 0o040, 0o053, 0o044, 0o000, 0o000, 0o000,
+    0o777, 0o357, 0o447, 0o637, 0o707, 0o737, 0o740, 0o750, 0o660, 0o360, 0o070, 0o276, 0o077, 0o000, 0o000, 0o000,
+    0o777, 0o567, 0o657, 0o757, 0o747, 0o755, 0o764, 0o772, 0o773, 0o572, 0o473, 0o276, 0o467, 0o000, 0o000, 0o000,
+];
+
+static CLASSIC: [u8; 192] = [
+    124,124,124,
+    0,0,252,
+    0,0,188,
+    68,40,188,
+    148,0,132,
+    168,0,32,
+    168,16,0,
+    136,20,0,
+    80,48,0,
+    0,120,0,
+    0,104,0,
+    0,88,0,
+    0,64,88,
+    0,0,0,
+    0,0,0,
+    0,0,0,
+    188,188,188,
+    0,120,248,
+    0,88,248,
+    104,68,252,
+    216,0,204,
+    228,0,88,
+    248,56,0,
+    228,92,16,
+    172,124,0,
+    0,184,0,
+    0,168,0,
+    0,168,68,
+    0,136,136,
+    0,0,0,
+    0,0,0,
+    0,0,0,
+    248,248,248,
+    60,188,252,
+    104,136,252,
+    152,120,248,
+    248,120,248,
+    248,88,152,
+    248,120,88,
+    252,160,68,
+    248,184,0,
+    184,248,24,
+    88,216,84,
+    88,248,152,
+    0,232,216,
+    120,120,120,
+    0,0,0,
+    0,0,0,
+    252,252,252,
+    164,228,252,
+    184,184,248,
+    216,184,248,
+    248,184,248,
+    248,164,192,
+    240,208,176,
+    252,224,168,
+    248,216,120,
+    216,248,120,
+    184,248,184,
+    184,248,216,
+    0,252,252,
+    248,216,248,
+    0,0,0,
+    0,0,0
+];
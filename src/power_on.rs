@@ -0,0 +1,65 @@
+use rand;
+use rand::prng::XorShiftRng;
+use rand::{Rng, SeedableRng};
+use std::fmt;
+
+// What ram holds when the console is switched on. Real hardware is somewhere between a pattern
+// and noise, and games that forget to clear their ram behave differently depending on it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RamFill {
+    Zeros,
+    Ones,
+    // Four $00 bytes then four $FF bytes, like many consoles and other emulators
+    Alternating,
+    // The seed makes a run repeatable, use the same one to get the same contents again
+    Random(u64),
+}
+
+// Each area gets its own random bytes, so filling them in any order gives the same result
+pub const CPU_RAM: u8 = 0;
+pub const PRG_RAM: u8 = 1;
+pub const OAM: u8 = 2;
+pub const PALETTE_RAM: u8 = 3;
+pub const VRAM: u8 = 4;
+
+impl RamFill {
+    // zeros, ff, alternating, random (with a new seed) or random:<seed>
+    pub fn parse(name: &str) -> Option<RamFill> {
+        match name {
+            "zeros" => Some(RamFill::Zeros),
+            "ff" => Some(RamFill::Ones),
+            "alternating" => Some(RamFill::Alternating),
+            "random" => Some(RamFill::Random(rand::random())),
+            _ if name.starts_with("random:") => name["random:".len()..].parse().ok().map(RamFill::Random),
+            _ => None
+        }
+    }
+
+    pub fn fill(&self, area: u8, data: &mut [u8]) {
+        match *self {
+            RamFill::Zeros => for val in data.iter_mut() { *val = 0 },
+            RamFill::Ones => for val in data.iter_mut() { *val = 0xFF },
+            RamFill::Alternating => for (i, val) in data.iter_mut().enumerate() {
+                *val = if i & 4 == 0 { 0 } else { 0xFF };
+            },
+            RamFill::Random(seed) => {
+                let mut rng_seed = [area; 16];
+                for i in 0..8 {
+                    rng_seed[i] = (seed >> (i * 8)) as u8;
+                }
+                XorShiftRng::from_seed(rng_seed).fill(data);
+            }
+        }
+    }
+}
+
+impl fmt::Display for RamFill {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RamFill::Zeros => write!(f, "zeros"),
+            RamFill::Ones => write!(f, "ff"),
+            RamFill::Alternating => write!(f, "alternating"),
+            RamFill::Random(seed) => write!(f, "random:{}", seed),
+        }
+    }
+}
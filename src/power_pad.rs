@@ -0,0 +1,152 @@
+use input::*;
+use memory::Mapper;
+use ppu::Ppu;
+use savestate::*;
+use std::any::Any;
+use std::io::Result;
+
+// See https://wiki.nesdev.com/w/index.php/Power_Pad
+// Buttons 1 to 12 are bits 0 to 11, numbered like side B of the mat:
+//  1  2  3  4
+//  5  6  7  8
+//  9 10 11 12
+pub fn power_pad_button(number: u8) -> u16 {
+    1 << (number - 1)
+}
+
+// The serial order of the buttons on bits 3 and 4 of the port
+const BIT_3_ORDER: [u8; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const BIT_4_ORDER: [u8; 4] = [4, 3, 12, 8];
+
+pub struct PowerPad {
+    pub buttons: u16,
+
+    strobe: bool,
+    count: u8,
+}
+
+impl PowerPad {
+    pub fn new() -> PowerPad {
+        PowerPad {
+            buttons: 0,
+            strobe: false,
+            count: 0,
+        }
+    }
+
+    fn pressed(&self, order: &[u8]) -> u8 {
+        match order.get(self.count as usize) {
+            Some(&number) => (self.buttons & power_pad_button(number) != 0) as u8,
+            None => 1
+        }
+    }
+}
+
+impl InputDevice for PowerPad {
+    fn name(&self) -> &'static str {
+        "power pad"
+    }
+
+    fn read(&mut self, _: u16, _: &mut Ppu, _: &mut Box<Mapper>) -> u8 {
+        let val = (self.pressed(&BIT_4_ORDER) << 4) | (self.pressed(&BIT_3_ORDER) << 3);
+        if !self.strobe && self.count < 8 {
+            self.count += 1;
+        }
+        val
+    }
+
+    fn write(&mut self, val: u8) {
+        self.strobe = val&0b0000001>0;
+        self.count = 0;
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+impl SaveState for PowerPad {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_bool(self.strobe);
+        out.write_u8(self.count);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        self.strobe = input.read_bool()?;
+        self.count = input.read_u8()?;
+        Ok(())
+    }
+}
+
+// The Famicom version of the mat goes in the expansion port. Writes to $4016 select rows of
+// the mat with bits 0-2 low, and $4017 bits 1-4 read back the columns, low when pressed
+// See https://wiki.nesdev.com/w/index.php/Family_Trainer_Mat
+pub struct FamilyTrainer {
+    pub buttons: u16,
+    rows: u8,
+}
+
+impl FamilyTrainer {
+    pub fn new() -> FamilyTrainer {
+        FamilyTrainer {
+            buttons: 0,
+            rows: 0b111,
+        }
+    }
+}
+
+impl InputDevice for FamilyTrainer {
+    fn name(&self) -> &'static str {
+        "family trainer"
+    }
+
+    fn read(&mut self, addr: u16, _: &mut Ppu, _: &mut Box<Mapper>) -> u8 {
+        if addr != 0x4017 {
+            return 0;
+        }
+
+        // Bit 2 selects buttons 1-4, bit 1 buttons 5-8 and bit 0 buttons 9-12
+        let mut pressed = 0;
+        for row in 0..3 {
+            if self.rows & (0b100 >> row) == 0 {
+                pressed |= (self.buttons >> (4*row)) & 0b1111;
+            }
+        }
+
+        // Bit 1 is the last button of the row and bit 4 the first
+        let mut val = 0;
+        for column in 0..4 {
+            if pressed & (1 << column) == 0 {
+                val |= 0b1_0000 >> column;
+            }
+        }
+        val
+    }
+
+    fn write(&mut self, val: u8) {
+        self.rows = val & 0b111;
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+impl SaveState for FamilyTrainer {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_u8(self.rows);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        self.rows = input.read_u8()?;
+        Ok(())
+    }
+}
@@ -4,76 +4,16 @@ use std::cmp;
 use image;
 use memory::*;
 use objekt;
+use savestate::*;
+use power_on::*;
+use palette::{self, Palette};
+use std::io::Result;
 
 pub type NesImageBuffer = image::ImageBuffer<image::Rgba<u8>, Vec<u8>>;
 
 static VBL: u32 = 21;
-static PALETTE: [u8; 192] = [
-    124,124,124,
-    0,0,252,
-    0,0,188,
-    68,40,188,
-    148,0,132,
-    168,0,32,
-    168,16,0,
-    136,20,0,
-    80,48,0,
-    0,120,0,
-    0,104,0,
-    0,88,0,
-    0,64,88,
-    0,0,0,
-    0,0,0,
-    0,0,0,
-    188,188,188,
-    0,120,248,
-    0,88,248,
-    104,68,252,
-    216,0,204,
-    228,0,88,
-    248,56,0,
-    228,92,16,
-    172,124,0,
-    0,184,0,
-    0,168,0,
-    0,168,68,
-    0,136,136,
-    0,0,0,
-    0,0,0,
-    0,0,0,
-    248,248,248,
-    60,188,252,
-    104,136,252,
-    152,120,248,
-    248,120,248,
-    248,88,152,
-    248,120,88,
-    252,160,68,
-    248,184,0,
-    184,248,24,
-    88,216,84,
-    88,248,152,
-    0,232,216,
-    120,120,120,
-    0,0,0,
-    0,0,0,
-    252,252,252,
-    164,228,252,
-    184,184,248,
-    216,184,248,
-    248,184,248,
-    248,164,192,
-    240,208,176,
-    252,224,168,
-    248,216,120,
-    216,248,120,
-    184,248,184,
-    184,248,216,
-    0,252,252,
-    248,216,248,
-    0,0,0,
-    0,0,0
-];
+// Bits of the io latch that aren't refreshed fade to 0 after about 600ms
+const IO_LATCH_DECAY_FRAMES: u8 = 36;
 
 struct MidframeState {
     count: u32,
@@ -87,8 +27,12 @@ struct MidframeState {
     sprite_size: u8,
 
     greyscale: bool,
+    mask_left_background: bool,
+    mask_left_sprites: bool,
     show_background: bool,
     show_sprites: bool,
+    // Red, green and blue in bits 0-2
+    emphasis: u8,
 
     mapper: Box<Mapper>,
 }
@@ -119,6 +63,11 @@ pub struct Ppu {
     ppu_chr_rom_delay_buffer: u8,
     enable_ppu_chr_delay: bool,
 
+    // The last value written to or read from a ppu register, returned for write-only registers
+    io_latch: u8,
+    // Frames left before each bit of the latch decays
+    io_latch_decay: [u8; 8],
+
     greyscale: bool,
     mask_left_background: bool, // 1: Show background in leftmost 8 pixels of screen, 0: Hide
     mask_left_sprites: bool,
@@ -132,17 +81,28 @@ pub struct Ppu {
     sprite_0_hit: bool,
     vertical_blanking: bool,
 
+    // The frame as 9 bit palette indices, the colour in bits 0-5 and the emphasis bits above it,
+    // a row at a time. output_canvas is this through the palette
+    pub frame: Vec<u16>,
     pub output_canvas: NesImageBuffer,
-    sprite_output: [[u16; 30*8]; 32*8],
-    bg_output: [[u16; 30*8]; 32*8],
-    sprite_priority: [[bool; 30*8]; 32*8],
-    pixel_greyscale: [[bool; 30*8]; 32*8],
+    pub palette: Palette,
+    // Off draws every sprite on a line instead of the first 8, which removes flicker in games that
+    // have more. The overflow flag still behaves like the real ppu
+    pub sprite_limit: bool,
+    sprite_output: Vec<[u16; 30*8]>,
+    bg_output: Vec<[u16; 30*8]>,
+    sprite_priority: Vec<[bool; 30*8]>,
+    pixel_greyscale: Vec<[bool; 30*8]>,
+    pixel_emphasis: Vec<[u8; 30*8]>,
     has_blanked: bool,
 
     states: Vec<MidframeState>,
-    has_drawn_sprite0_background: bool,
+    // The line sprite 0 hit was last checked on, and the dot on it where the hit is still to come
+    sprite_0_line: u32,
+    sprite_0_dot: Option<u32>,
 
     last_ticked_scanline: u32,
+    output_up_to_date: bool,
 }
 
 impl Ppu {
@@ -173,6 +133,9 @@ impl Ppu {
             ppu_chr_rom_delay_buffer: 0,
             enable_ppu_chr_delay: false,
 
+            io_latch: 0,
+            io_latch_decay: [0; 8],
+
             greyscale: false,
             mask_left_background: false,
             mask_left_sprites: false,
@@ -186,19 +149,62 @@ impl Ppu {
             sprite_0_hit: false,
             vertical_blanking: false,
 
+            frame: vec![0; 32*8 * 30*8],
             output_canvas: make_canvas(32 * 8, 30 * 8),
-            sprite_output: [[0; 30*8]; 32*8],
-            bg_output: [[0; 30*8]; 32*8],
-            sprite_priority: [[false; 30*8]; 32*8],
-            pixel_greyscale: [[false; 30*8]; 32*8],
+            palette: Palette::default(),
+            sprite_limit: true,
+            sprite_output: vec![[0; 30*8]; 32*8],
+            bg_output: vec![[0; 30*8]; 32*8],
+            sprite_priority: vec![[false; 30*8]; 32*8],
+            pixel_greyscale: vec![[false; 30*8]; 32*8],
+            pixel_emphasis: vec![[0; 30*8]; 32*8],
             has_blanked: false,
 
             states: vec![],
-            has_drawn_sprite0_background: false,
+            sprite_0_line: 0,
+            sprite_0_dot: None,
             last_ticked_scanline: 0,
+            output_up_to_date: false,
+        }
+    }
+
+    // Only the 6 bits of a colour are stored in palette ram
+    pub fn power_on(&mut self, fill: &RamFill) {
+        fill.fill(VRAM, &mut self.vram);
+        fill.fill(OAM, &mut self.oam);
+        fill.fill(PALETTE_RAM, &mut self.palette_rame);
+        for val in self.palette_rame.iter_mut() {
+            *val &= 0x3F;
         }
     }
 
+    // The reset button clears the control, mask and scroll registers, vram and oam are left alone
+    // See https://wiki.nesdev.com/w/index.php/PPU_power_up_state
+    pub fn reset(&mut self, cpu: &Cpu, mapper: &Box<Mapper>) {
+        self.nametable = 0;
+        self.vram_inc = 0;
+        self.spritetable = 0;
+        self.backgroundtable = 0;
+        self.sprite_size = 0;
+        self.ppu_mss = false;
+        self.generate_nmi = false;
+
+        self.greyscale = false;
+        self.mask_left_background = false;
+        self.mask_left_sprites = false;
+        self.show_background = false;
+        self.show_sprites = false;
+        self.em_red = false;
+        self.em_green = false;
+        self.em_blue = false;
+
+        self.ppuscroll_x = 0;
+        self.ppuscroll_y = 0;
+        self.ppuscroll_ppuaddr_pick = false;
+        self.ppu_chr_rom_delay_buffer = 0;
+        self.push_state(cpu, mapper);
+    }
+
     fn make_midframe_state(&self, count: u32, mapper: &Box<Mapper>) -> MidframeState {
         MidframeState {
             count: count,
@@ -209,8 +215,11 @@ impl Ppu {
             backgroundtable: self.backgroundtable,
             sprite_size: self.sprite_size,
             greyscale: self.greyscale,
+            mask_left_background: self.mask_left_background,
+            mask_left_sprites: self.mask_left_sprites,
             show_background: self.show_background,
             show_sprites: self.show_sprites,
+            emphasis: (self.em_red as u8) | ((self.em_green as u8)<<1) | ((self.em_blue as u8)<<2),
             mapper: objekt::clone_box(&**mapper),
         }
     }
@@ -218,8 +227,10 @@ impl Ppu {
     pub fn push_state(&mut self, cpu: &Cpu, mapper: &Box<Mapper>) {
         let state = self.make_midframe_state(cpu.count, mapper);
         self.states.push(state);
+        self.output_up_to_date = false;
     }
 
+    // Registers only drive some bits of the result, the rest come from the io latch
     pub fn read_main(&mut self, mapper: &mut Box<Mapper>, addr: u16) -> u8 {
         match addr as usize {
             0x2002 => {
@@ -227,11 +238,19 @@ impl Ppu {
                 self.vertical_blanking = false;
                 self.ppuscroll_ppuaddr_pick = false;
 
-                ((blanking as u8)<<7)
+                let status = ((blanking as u8)<<7)
                     + ((self.sprite_0_hit as u8)<<6)
-                    + ((self.sprite_overflow as u8)<<5)
+                    + ((self.sprite_overflow as u8)<<5);
+                self.refresh_io_latch(status, 0b11100000)
+            },
+            0x2004 => {
+                let mut val = self.oam[self.oamaddr as usize];
+                // Bits 2-4 of the sprite attributes don't exist
+                if self.oamaddr & 3 == 2 {
+                    val &= 0b11100011;
+                }
+                self.refresh_io_latch(val, 0xFF)
             },
-            0x2003 => self.oam[self.oamaddr as usize],
             0x2007 => {
                 let addr = ((self.ppuaddr_lo as u16)&0x00FF)
                     + (((self.ppuaddr_hi as u16)&0xFF)<<8);
@@ -241,15 +260,40 @@ impl Ppu {
                 self.enable_ppu_chr_delay = false;
 
                 self.increment_ppuaddr();
-                val
+                // Palette ram is only 6 bits wide
+                if addr & 0x3FFF >= 0x3F00 {
+                    self.refresh_io_latch(val, 0b00111111)
+                } else {
+                    self.refresh_io_latch(val, 0xFF)
+                }
             },
-            _ => {
-                panic!("Read from invalid main address {:X}", addr);
+            _ => self.io_latch
+        }
+    }
+
+    fn refresh_io_latch(&mut self, val: u8, bits: u8) -> u8 {
+        self.io_latch = (self.io_latch & !bits) | (val & bits);
+        for bit in 0..8 {
+            if bits & (1<<bit) != 0 {
+                self.io_latch_decay[bit] = IO_LATCH_DECAY_FRAMES;
+            }
+        }
+        self.io_latch
+    }
+
+    fn decay_io_latch(&mut self) {
+        for bit in 0..8 {
+            if self.io_latch_decay[bit] > 0 {
+                self.io_latch_decay[bit] -= 1;
+                if self.io_latch_decay[bit] == 0 {
+                    self.io_latch &= !(1<<bit);
+                }
             }
         }
     }
 
     pub fn write_main(&mut self, mapper: &mut Box<Mapper>, addr: u16, val: u8, cpu: &Cpu) {
+        self.refresh_io_latch(val, 0xFF);
         match addr as usize {
             0x2000 => {
                 self.nametable              = val&0b00000011;
@@ -307,23 +351,11 @@ impl Ppu {
                 let addr = ((self.ppuaddr_lo as u16)&0x00FF)
                     + (((self.ppuaddr_hi as u16)&0xFF)<<8);
                 self.write(mapper, addr, val);
-                self.increment_ppuaddr()
+                self.increment_ppuaddr();
+                self.output_up_to_date = false;
             },
-            _ => {
-                panic!("Write to invalid main address {:X}", addr);
-            }
-        }
-    }
-
-    pub fn ppudma(&mut self, mapper: &mut Box<Mapper>, val: u8, cpu: &mut Cpu, mem: &mut Mem) {
-        cpu.count += 1;
-        cpu.count += cpu.count%2;
-        cpu.count += 512;
-
-        let addr = ((val as u16)&0x00FF)<<8;
-        for i in 0..=255 {
-            self.oam[self.oamaddr.wrapping_add(i) as usize]
-                = mem.read(mapper, addr + i as u16);
+            // $2002 is read-only, writing it only sets the io latch
+            _ => ()
         }
     }
 
@@ -332,6 +364,12 @@ impl Ppu {
 
         while self.last_ticked_scanline < y && self.last_ticked_scanline < 262 && (cpu.count*3)%341 > 260 {
             self.last_ticked_scanline += 1;
+            if let Some(line) = self.current_scanline() {
+                if (self.show_background || self.show_sprites) && !self.sprite_overflow {
+                    let height = if self.sprite_size == 0 { 8 } else { 16 };
+                    self.sprite_overflow = self.evaluate_sprites(line as u16, height).1;
+                }
+            }
             if y >= 19 && y < 260 {
                 if mapper.ppu_scanline(cpu, self) { self.push_state(cpu, mapper); }
             }
@@ -341,6 +379,7 @@ impl Ppu {
             self.has_blanked = true;
             self.vertical_blanking = true;
             self.oamaddr = 0;
+            self.decay_io_latch();
 
             if self.generate_nmi {
                 cpu.nmi();
@@ -350,49 +389,68 @@ impl Ppu {
         if y >= VBL && self.has_blanked {
             self.has_blanked = false;
             self.vertical_blanking = false;
-            self.has_drawn_sprite0_background = false;
             self.sprite_0_hit = false;
+            self.sprite_overflow = false;
 
             self.states.clear();
             self.push_state(cpu, mapper);
         }
 
-        let sprite_0_y = self.oam[self.oamaddr as usize] as u32 + 1;
-        if self.show_sprites && self.show_background && !self.sprite_0_hit &&
-                y >= sprite_0_y + VBL + 1 && y < sprite_0_y + VBL + 1 + 8 {
-            let idx = self.states.len()-1;
-            let (sprite_0_x,_,_, pattern_addr, _, _, _, _) = self.get_sprite_attrs(0, idx);
-
-            if !self.has_drawn_sprite0_background {
-                self.has_drawn_sprite0_background = true;
-
-                // This could be more efficient, but what the hell
-                self.show_sprites = false;
-                self.draw_with_state(idx, sprite_0_y as u16, sprite_0_y as u16 + 8, mapper);
-                self.show_sprites = true;
+        // The line is checked as it starts, then the flag is set once the ppu reaches the dot
+        if y != self.sprite_0_line {
+            // The cpu ran past the end of the line before looking
+            if self.sprite_0_dot.is_some() {
+                self.sprite_0_hit = true;
+            }
+            self.sprite_0_line = y;
+            self.sprite_0_dot = None;
+            if self.show_background && self.show_sprites && !self.sprite_0_hit && y > VBL && y <= VBL + 30*8 {
+                self.sprite_0_dot = self.find_sprite_0_hit((y - VBL - 1) as u16, mapper);
             }
+        }
 
-            let py = y as u16 - sprite_0_y as u16  - VBL as u16 - 1;
+        if let Some(dot) = self.sprite_0_dot {
+            if (cpu.count*3)%341 >= dot {
+                self.sprite_0_hit = true;
+                self.sprite_0_dot = None;
+            }
+        }
+    }
 
-            // Who cares about 16px sprites!
-            let lo = self.read(mapper, pattern_addr + py);
-            let hi = self.read(mapper, pattern_addr + py + 8);
+    // The dot where an opaque pixel of sprite 0 first lands on an opaque background pixel. Hits
+    // can't happen at x=255, or in the left 8 pixels when either is clipped there
+    // See https://wiki.nesdev.com/w/index.php/PPU_OAM#Sprite_zero_hits
+    fn find_sprite_0_hit(&mut self, line: u16, mapper: &mut Box<Mapper>) -> Option<u32> {
+        let idx = self.states.len() - 1;
+        let (x, y, height, pattern_addr, _, _, fh, fv) = self.get_sprite_attrs(0, idx);
+        if line < y || line >= y + height as u16 {
+            return None;
+        }
 
-            for px in 0..8 {
-                let mask = 0b00000001<<(7-px);
-                let solid = (((lo&mask)>>(7-px))
-                    + (((hi&mask)>>(7-px))<<1)) != 0;
+        self.draw_background(idx, line, line, mapper);
 
-                if !solid { continue; }
-                if self.bg_output[sprite_0_x as usize + px as usize]
-                        [sprite_0_y as usize + py as usize]&0b00000011 == 0 {
-                    continue;
-                }
+        let row = if fv { y + height as u16 - 1 - line } else { line - y };
+        let lo = self.read(mapper, pattern_addr + 16*(row/8) + row%8);
+        let hi = self.read(mapper, pattern_addr + 16*(row/8) + row%8 + 8);
+        let left_clipped = !self.mask_left_background || !self.mask_left_sprites;
 
-                self.sprite_0_hit = true;
+        for px in 0..8 {
+            let real_x = x as u32 + px;
+            if real_x >= 255 {
                 break;
             }
+            if left_clipped && real_x < 8 {
+                continue;
+            }
+
+            let bit = if fh { px } else { 7 - px };
+            let solid = ((lo >> bit) | (hi >> bit)) & 1 != 0;
+            if solid && self.bg_output[real_x as usize][line as usize]&0b00000011 != 0 {
+                // Pixel x comes out on dot x+1
+                return Some(real_x + 1);
+            }
         }
+        None
     }
 
     fn draw_tile(&mut self, state_idx: usize, nametable: u8, tile_x: u16, tile_y: u16,
@@ -428,20 +486,25 @@ impl Ppu {
             let hi = self.read(mapper, pattern_addr + y + 8);
 
             for x in x_offset..=(screen_x_end-screen_x_start+x_offset) {
+                let screen_x = (x+screen_x_start-x_offset) as usize;
+                let screen_y = (y+screen_y_start-y_offset) as usize;
+
                 let mask = 0b00000001<<(7-x);
                 let mut palette_idx = ((lo&mask)>>(7-x)) as u16
                     + (((hi&mask)>>(7-x))<<1) as u16;
                 if palette_idx != 0 {
                     palette_idx += colour_bits as u16;
                 }
+                if screen_x < 8 && !self.states[state_idx].mask_left_background {
+                    palette_idx = 0;
+                }
 
                 if self.states[state_idx].show_background {
-                    self.bg_output[(x+screen_x_start-x_offset) as usize][(y+screen_y_start-y_offset) as usize]
-                        = 0x3F00 + palette_idx;
+                    self.bg_output[screen_x][screen_y] = 0x3F00 + palette_idx;
                 }
 
-                self.pixel_greyscale[(x+screen_x_start-x_offset) as usize][(y+screen_y_start-y_offset) as usize]
-                    = self.states[state_idx].greyscale;
+                self.pixel_greyscale[screen_x][screen_y] = self.states[state_idx].greyscale;
+                self.pixel_emphasis[screen_x][screen_y] = self.states[state_idx].emphasis;
             }
         }
     }
@@ -479,6 +542,12 @@ impl Ppu {
     fn draw_with_state(&mut self, state_idx: usize, state_start_y: u16, state_end_y: u16,
                        _: &mut Box<Mapper>) {
         let mut current_mapper = objekt::clone_box(&*self.states[state_idx].mapper);
+        self.draw_background(state_idx, state_start_y, state_end_y, &mut current_mapper);
+        self.draw_sprites(state_idx, state_start_y, state_end_y, &mut current_mapper);
+    }
+
+    fn draw_background(&mut self, state_idx: usize, state_start_y: u16, state_end_y: u16,
+                       mapper: &mut Box<Mapper>) {
         let sx = self.states[state_idx].ppuscroll_x as u16;
         let sy = self.states[state_idx].ppuscroll_y as u16;
         let base_nt = self.states[state_idx].nametable;
@@ -522,65 +591,124 @@ impl Ppu {
                 }
 
                 self.draw_tile(state_idx, n, tile_x, tile_y, start_x, start_y, end_x, end_y,
-                          off_x, off_y, &mut current_mapper);
+                          off_x, off_y, mapper);
             }
         }
+    }
+
+    fn draw_sprites(&mut self, state_idx: usize, state_start_y: u16, state_end_y: u16,
+                    mapper: &mut Box<Mapper>) {
+        let height = if self.states[state_idx].sprite_size == 0 { 8 } else { 16 };
+        for line in state_start_y..=state_end_y {
+            if line as u32 >= self.output_canvas.height() {
+                break;
+            }
+
+            let (sprites, _) = self.evaluate_sprites(line, height);
+            for &s in &sprites {
+                let (x, y, height, pattern_addr, palette, priority, fh, fv) = self.get_sprite_attrs(s, state_idx);
 
-        for s in 0..64 {
-            let (x,  y, height, pattern_addr, palette, priority, fh, fv) = self.get_sprite_attrs(s, state_idx);
-            if y >= 0xF0 { continue; }
-            if y <= 2 { continue; }
-            if y > state_end_y { continue; }
-            if y + (height as u16) < state_start_y { continue; }
-
-            for i in 0..(height/8) {
-                for py in 0..8 {
-                    let lo = self.read(&mut current_mapper, pattern_addr + 16*i as u16 + py);
-                    let hi = self.read(&mut current_mapper, pattern_addr + 16*i as u16 + py + 8);
-
-                    for px in 0..8 {
-                        let real_x = if !fh {
-                            x as u32 + px as u32
-                        } else {
-                            x as u32 + 7 - px as u32
-                        };
-                        let real_y = if !fv {
-                            y as u32 + (8 * i as u32 + py as u32)
-                        } else {
-                            y as u32 + (8 * (height/8) as u32 - 1) - (8 * i as u32 + py as u32)
-                        };
-
-                        if real_x >= self.output_canvas.width()
-                            || real_y > state_end_y as u32
-                            || real_y < state_start_y as u32 {
-                            continue;
-                        }
-
-                        if self.sprite_output[real_x as usize][real_y as usize] != 0 {
-                            continue;
-                        }
-
-                        let mask = 0b00000001 << (7 - px);
-                        let palette_idx = ((lo & mask) >> (7 - px))
-                            + (((hi & mask) >> (7 - px)) << 1);
-                        if palette_idx == 0 {
-                            continue;
-                        }
-
-                        if self.states[state_idx].show_sprites {
-                            self.sprite_output[real_x as usize][real_y as usize]
-                                = palette_idx as u16 + palette;
-                            self.sprite_priority[real_x as usize][real_y as usize] = priority;
-                        }
+                let row = if fv { y + height as u16 - 1 - line } else { line - y };
+                let lo = self.read(mapper, pattern_addr + 16*(row/8) + row%8);
+                let hi = self.read(mapper, pattern_addr + 16*(row/8) + row%8 + 8);
+
+                for px in 0..8 {
+                    let real_x = if !fh {
+                        x as u32 + px as u32
+                    } else {
+                        x as u32 + 7 - px as u32
+                    };
+
+                    if real_x >= self.output_canvas.width() {
+                        continue;
+                    }
+                    if real_x < 8 && !self.states[state_idx].mask_left_sprites {
+                        continue;
+                    }
+
+                    // Earlier sprites win, even when they are behind the background
+                    if self.sprite_output[real_x as usize][line as usize] != 0 {
+                        continue;
+                    }
+
+                    let mask = 0b00000001 << (7 - px);
+                    let palette_idx = ((lo & mask) >> (7 - px))
+                        + (((hi & mask) >> (7 - px)) << 1);
+                    if palette_idx == 0 {
+                        continue;
+                    }
+
+                    if self.states[state_idx].show_sprites {
+                        self.sprite_output[real_x as usize][line as usize]
+                            = palette_idx as u16 + palette;
+                        self.sprite_priority[real_x as usize][line as usize] = priority;
                     }
                 }
             }
         }
     }
 
+    // Which sprites are on a line, in priority order, and whether the sprite overflow flag gets set.
+    // Like the real ppu, after finding 8 sprites the search moves diagonally through oam, reading
+    // the wrong byte of each sprite as its y, so the flag can be wrong both ways
+    // See https://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
+    fn evaluate_sprites(&self, line: u16, height: u16) -> (Vec<u8>, bool) {
+        let oam_byte = |n: u8, m: u8| self.oam[self.oamaddr.wrapping_add(4*n + m) as usize] as u16;
+        let on_line = |y: u16| line > y && line <= y + height;
+
+        let mut sprites = vec![];
+        let mut n = 0;
+        while n < 64 && sprites.len() < 8 {
+            if on_line(oam_byte(n, 0)) {
+                sprites.push(n);
+            }
+            n += 1;
+        }
+        let unchecked = n;
+
+        let mut overflow = false;
+        let mut m = 0;
+        while n < 64 {
+            if on_line(oam_byte(n, m)) {
+                overflow = true;
+                break;
+            }
+            n += 1;
+            m = (m + 1) % 4;
+        }
+
+        if !self.sprite_limit {
+            sprites.extend((unchecked..64).filter(|&s| on_line(oam_byte(s, 0))));
+        }
+        (sprites, overflow)
+    }
+
     pub fn prepare_draw(&mut self, mapper: &mut Box<Mapper>) {
 //        if self.last_ticked_scanline != 262 { panic!("Last ticked scanline is {}", self.last_ticked_scanline); }
         self.last_ticked_scanline = 0;
+        self.render(mapper);
+    }
+
+    // The visible line being drawn, or None during vertical blank
+    pub fn current_scanline(&self) -> Option<u32> {
+        if self.last_ticked_scanline >= VBL && self.last_ticked_scanline < VBL + 30*8 {
+            Some(self.last_ticked_scanline - VBL)
+        } else {
+            None
+        }
+    }
+
+    // Luma of a pixel in the frame being drawn, as far as it is known. This is what the zapper's light sensor sees
+    pub fn pixel_brightness(&mut self, x: u32, y: u32, mapper: &mut Box<Mapper>) -> u8 {
+        if !self.output_up_to_date {
+            self.render(mapper);
+        }
+
+        palette::luma(self.frame[(y * self.output_canvas.width() + x) as usize])
+    }
+
+    fn render(&mut self, mapper: &mut Box<Mapper>) {
+        self.output_up_to_date = true;
 
         for x in 0..self.output_canvas.width() {
             for y in 0..self.output_canvas.height() {
@@ -617,12 +745,22 @@ impl Ppu {
                     bg
                 };
 
-                let hsv = (self.read(mapper, p_idx) & mask) as usize;
-                self.output_canvas.put_pixel(x, y, image::Rgba([*PALETTE.get(hsv * 3).unwrap_or(&0),
-                    *PALETTE.get(hsv * 3 + 1).unwrap_or(&0),
-                    *PALETTE.get(hsv * 3 + 2).unwrap_or(&0), 0xFF]));
+                let hsv = self.read(mapper, p_idx) & mask;
+                self.frame[(y * self.output_canvas.width() + x) as usize]
+                    = hsv as u16 | (self.pixel_emphasis[x as usize][y as usize] as u16) << 6;
             }
         }
+
+        self.update_canvas();
+    }
+
+    // Converts the frame to RGB, again after changing the palette to see the change straight away
+    pub fn update_canvas(&mut self) {
+        let width = self.output_canvas.width();
+        for (i, &idx) in self.frame.iter().enumerate() {
+            let rgb = self.palette.rgb(idx);
+            self.output_canvas.put_pixel(i as u32 % width, i as u32 / width, image::Rgba([rgb[0], rgb[1], rgb[2], 0xFF]));
+        }
     }
 
     pub fn increment_ppuaddr(&mut self) {
@@ -634,6 +772,98 @@ impl Ppu {
     }
 }
 
+// The midframe states are only needed to draw the frame in progress, so they aren't saved
+impl SaveState for Ppu {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_bytes(&self.vram);
+        out.write_bytes(&self.palette_rame);
+        out.write_u8(self.oamaddr);
+        out.write_bytes(&self.oam);
+
+        out.write_u8(self.ppuscroll_x);
+        out.write_u8(self.ppuscroll_y);
+        out.write_bool(self.ppuscroll_ppuaddr_pick);
+        out.write_u8(self.ppuaddr_hi);
+        out.write_u8(self.ppuaddr_lo);
+
+        out.write_u8(self.nametable);
+        out.write_u8(self.vram_inc);
+        out.write_u8(self.spritetable);
+        out.write_u8(self.backgroundtable);
+        out.write_u8(self.sprite_size);
+        out.write_bool(self.ppu_mss);
+        out.write_bool(self.generate_nmi);
+        out.write_u8(self.ppu_chr_rom_delay_buffer);
+        out.write_u8(self.io_latch);
+        out.write_bytes(&self.io_latch_decay);
+
+        out.write_bool(self.greyscale);
+        out.write_bool(self.mask_left_background);
+        out.write_bool(self.mask_left_sprites);
+        out.write_bool(self.show_background);
+        out.write_bool(self.show_sprites);
+        out.write_bool(self.em_red);
+        out.write_bool(self.em_green);
+        out.write_bool(self.em_blue);
+
+        out.write_bool(self.sprite_overflow);
+        out.write_bool(self.sprite_0_hit);
+        out.write_bool(self.vertical_blanking);
+        out.write_bool(self.has_blanked);
+        out.write_u32(self.sprite_0_line);
+        out.write_bool(self.sprite_0_dot.is_some());
+        out.write_u32(self.sprite_0_dot.unwrap_or(0));
+        out.write_u32(self.last_ticked_scanline);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        input.read_bytes(&mut self.vram)?;
+        input.read_bytes(&mut self.palette_rame)?;
+        self.oamaddr = input.read_u8()?;
+        input.read_bytes(&mut self.oam)?;
+
+        self.ppuscroll_x = input.read_u8()?;
+        self.ppuscroll_y = input.read_u8()?;
+        self.ppuscroll_ppuaddr_pick = input.read_bool()?;
+        self.ppuaddr_hi = input.read_u8()?;
+        self.ppuaddr_lo = input.read_u8()?;
+
+        self.nametable = input.read_u8()?;
+        self.vram_inc = input.read_u8()?;
+        self.spritetable = input.read_u8()?;
+        self.backgroundtable = input.read_u8()?;
+        self.sprite_size = input.read_u8()?;
+        self.ppu_mss = input.read_bool()?;
+        self.generate_nmi = input.read_bool()?;
+        self.ppu_chr_rom_delay_buffer = input.read_u8()?;
+        self.io_latch = input.read_u8()?;
+        input.read_bytes(&mut self.io_latch_decay)?;
+
+        self.greyscale = input.read_bool()?;
+        self.mask_left_background = input.read_bool()?;
+        self.mask_left_sprites = input.read_bool()?;
+        self.show_background = input.read_bool()?;
+        self.show_sprites = input.read_bool()?;
+        self.em_red = input.read_bool()?;
+        self.em_green = input.read_bool()?;
+        self.em_blue = input.read_bool()?;
+
+        self.sprite_overflow = input.read_bool()?;
+        self.sprite_0_hit = input.read_bool()?;
+        self.vertical_blanking = input.read_bool()?;
+        self.has_blanked = input.read_bool()?;
+        self.sprite_0_line = input.read_u32()?;
+        let hit_pending = input.read_bool()?;
+        let dot = input.read_u32()?;
+        self.sprite_0_dot = if hit_pending { Some(dot) } else { None };
+        self.last_ticked_scanline = input.read_u32()?;
+
+        self.states.clear();
+        self.output_up_to_date = false;
+        Ok(())
+    }
+}
+
 pub fn make_canvas(width: u32, height: u32) -> NesImageBuffer {
     image::ImageBuffer::new(width, height)
 }
@@ -705,7 +935,7 @@ impl Mem for Ppu {
             0x3F14 => self.write(mapper, 0x3F04, val),
             0x3F18 => self.write(mapper, 0x3F08, val),
             0x3F1C => self.write(mapper, 0x3F0C, val),
-            0x3F00..=0x3F1F => self.palette_rame[addr as usize - 0x3F00] = val,
+            0x3F00..=0x3F1F => self.palette_rame[addr as usize - 0x3F00] = val & 0x3F,
             0x3F20..=0x3FFF => self.write(mapper, mirror_addr(0x3F20..=0x3FFF, 0x3F00..=0x3F1F, addr), val),
             0x4000..=0xFFFF => self.write(mapper, mirror_addr(0x0000..=0x3FFF, 0x4000..=0xFFFF, addr), val),
             _ => {
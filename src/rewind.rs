@@ -0,0 +1,180 @@
+use nes::Nes;
+use std::collections::VecDeque;
+
+// Each entry turns the capture after it back into the one before, which is all rewinding needs
+enum Snapshot {
+    Delta(Vec<u8>),
+    Full(Vec<u8>),
+}
+
+impl Snapshot {
+    fn len(&self) -> usize {
+        match *self {
+            Snapshot::Delta(ref data) => data.len(),
+            Snapshot::Full(ref data) => data.len(),
+        }
+    }
+}
+
+pub struct RewindBuffer {
+    interval: u32,
+    budget: usize,
+    frames_since_capture: u32,
+
+    newest: Option<Vec<u8>>,
+    history: VecDeque<Snapshot>,
+    used: usize,
+}
+
+impl RewindBuffer {
+    // Captures a state every interval frames, dropping the oldest ones to stay within budget bytes
+    pub fn new(interval: u32, budget: usize) -> RewindBuffer {
+        RewindBuffer {
+            interval: if interval == 0 { 1 } else { interval },
+            budget: budget,
+            frames_since_capture: 0,
+
+            newest: None,
+            history: VecDeque::new(),
+            used: 0,
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.newest = None;
+        self.history.clear();
+        self.used = 0;
+        self.frames_since_capture = 0;
+    }
+
+    pub fn len(&self) -> usize {
+        self.history.len() + if self.newest.is_some() { 1 } else { 0 }
+    }
+
+    // Call once after every emulated frame
+    pub fn capture(&mut self, nes: &Nes) {
+        self.frames_since_capture += 1;
+        if self.frames_since_capture < self.interval {
+            return;
+        }
+        self.frames_since_capture = 0;
+
+        let state = nes.save_state();
+        if let Some(previous) = self.newest.take() {
+            self.used -= previous.len();
+            let snapshot = if previous.len() == state.len() {
+                Snapshot::Delta(encode_delta(&state, &previous))
+            } else {
+                Snapshot::Full(previous)
+            };
+
+            self.used += snapshot.len();
+            self.history.push_back(snapshot);
+        }
+
+        self.used += state.len();
+        self.newest = Some(state);
+
+        while self.used > self.budget {
+            match self.history.pop_front() {
+                Some(snapshot) => self.used -= snapshot.len(),
+                None => break,
+            }
+        }
+    }
+
+    // Loads the newest capture and forgets it, returning false once there is nothing left
+    pub fn rewind(&mut self, nes: &mut Nes) -> bool {
+        let state = match self.newest.take() {
+            Some(state) => state,
+            None => return false,
+        };
+        self.used -= state.len();
+        self.frames_since_capture = 0;
+
+        if let Some(snapshot) = self.history.pop_back() {
+            self.used -= snapshot.len();
+            let previous = match snapshot {
+                Snapshot::Delta(delta) => decode_delta(&delta, &state),
+                Snapshot::Full(previous) => previous,
+            };
+
+            self.used += previous.len();
+            self.newest = Some(previous);
+        }
+
+        nes.load_state(&state).is_ok()
+    }
+}
+
+// The xor of two states is mostly zeros, so it is stored as runs of (zeros, literal bytes)
+fn encode_delta(from: &[u8], to: &[u8]) -> Vec<u8> {
+    let mut out = vec![];
+    let mut i = 0;
+
+    while i < from.len() {
+        let zeros_start = i;
+        while i < from.len() && from[i] == to[i] {
+            i += 1;
+        }
+        write_varint(&mut out, i - zeros_start);
+
+        let literal_start = i;
+        while i < from.len() && from[i] != to[i] {
+            i += 1;
+        }
+        write_varint(&mut out, i - literal_start);
+        for j in literal_start..i {
+            out.push(from[j] ^ to[j]);
+        }
+    }
+
+    out
+}
+
+fn decode_delta(delta: &[u8], from: &[u8]) -> Vec<u8> {
+    let mut out = from.to_vec();
+    let mut pos = 0;
+    let mut i = 0;
+
+    while pos < delta.len() {
+        i += read_varint(delta, &mut pos);
+
+        let literals = read_varint(delta, &mut pos);
+        for _ in 0..literals {
+            out[i] ^= delta[pos];
+            pos += 1;
+            i += 1;
+        }
+    }
+
+    out
+}
+
+fn write_varint(out: &mut Vec<u8>, mut val: usize) {
+    loop {
+        if val < 0x80 {
+            out.push(val as u8);
+            return;
+        }
+
+        out.push((val as u8 & 0x7F) | 0x80);
+        val >>= 7;
+    }
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> usize {
+    let mut val = 0;
+    let mut shift = 0;
+
+    loop {
+        let b = data[*pos];
+        *pos += 1;
+        val |= ((b & 0x7F) as usize) << shift;
+
+        if b & 0x80 == 0 {
+            return val;
+        }
+        shift += 7;
+    }
+}
@@ -0,0 +1,142 @@
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+
+// Every component writes its fields in a fixed order, so bump this whenever any of them change
+pub const STATE_VERSION: u32 = 8;
+pub const STATE_MAGIC: [u8; 4] = *b"NESS";
+
+pub trait SaveState {
+    fn save_state(&self, out: &mut StateWriter);
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()>;
+}
+
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter {
+            data: vec![],
+        }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_u8(&mut self, val: u8) {
+        self.data.push(val);
+    }
+
+    pub fn write_bool(&mut self, val: bool) {
+        self.write_u8(val as u8);
+    }
+
+    pub fn write_u16(&mut self, val: u16) {
+        self.write_u8((val&0x00FF) as u8);
+        self.write_u8(((val&0xFF00)>>8) as u8);
+    }
+
+    pub fn write_u32(&mut self, val: u32) {
+        self.write_u16((val&0xFFFF) as u16);
+        self.write_u16(((val>>16)&0xFFFF) as u16);
+    }
+
+    pub fn write_u64(&mut self, val: u64) {
+        self.write_u32((val&0xFFFF_FFFF) as u32);
+        self.write_u32(((val>>32)&0xFFFF_FFFF) as u32);
+    }
+
+    // For fixed size arrays, where the reader already knows the length
+    pub fn write_bytes(&mut self, val: &[u8]) {
+        self.data.extend_from_slice(val);
+    }
+
+    pub fn write_vec(&mut self, val: &[u8]) {
+        self.write_u32(val.len() as u32);
+        self.write_bytes(val);
+    }
+}
+
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader {
+            data: data,
+            pos: 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8> {
+        match self.data.get(self.pos) {
+            Some(&val) => {
+                self.pos += 1;
+                Ok(val)
+            },
+            None => Err(Error::new(ErrorKind::UnexpectedEof, "Save state is truncated"))
+        }
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16> {
+        let lo = self.read_u8()? as u16;
+        let hi = self.read_u8()? as u16;
+        Ok(lo + (hi<<8))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32> {
+        let lo = self.read_u16()? as u32;
+        let hi = self.read_u16()? as u32;
+        Ok(lo + (hi<<16))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64> {
+        let lo = self.read_u32()? as u64;
+        let hi = self.read_u32()? as u64;
+        Ok(lo + (hi<<32))
+    }
+
+    pub fn read_bytes(&mut self, out: &mut [u8]) -> Result<()> {
+        let end = self.pos + out.len();
+        if end > self.data.len() {
+            return Err(Error::new(ErrorKind::UnexpectedEof, "Save state is truncated"));
+        }
+
+        out.copy_from_slice(&self.data[self.pos..end]);
+        self.pos = end;
+        Ok(())
+    }
+
+    pub fn read_vec(&mut self) -> Result<Vec<u8>> {
+        let len = self.read_u32()? as usize;
+        let mut out = vec![0; len];
+        self.read_bytes(&mut out)?;
+        Ok(out)
+    }
+
+    // Used for buffers like prg ram, where the size comes from the rom and must not change
+    pub fn read_vec_into(&mut self, out: &mut Vec<u8>) -> Result<()> {
+        let val = self.read_vec()?;
+        if val.len() != out.len() {
+            return Err(Error::new(ErrorKind::InvalidData,
+                                  format!("Save state has {} bytes where {} were expected", val.len(), out.len())));
+        }
+
+        *out = val;
+        Ok(())
+    }
+}
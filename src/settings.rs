@@ -1 +1,472 @@
-pub const DEBUG: bool = false;
\ No newline at end of file
+use piston::input::{Button, Key, MouseButton};
+use sdl2::controller;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use nes_emulator::controller::*;
+use nes_emulator::palette::NtscSettings;
+use nes_emulator::power_on::RamFill;
+
+pub const DEBUG: bool = false;
+
+pub const SETTINGS_PATH: &str = "settings.ini";
+
+// Written out on the first run. Settings missing from the file keep these values
+pub const DEFAULT_SETTINGS: &str = "; Any setting can also be given on the command line, as --section.key=value
+
+[general]
+rom = assets/smb3.nes
+; Only ntsc is emulated
+region = ntsc
+; What ram holds at power on: zeros, ff, alternating, random or random:<seed> to repeat a random run
+power_on = zeros
+
+[video]
+; The window is 256x240 times this
+scale = 4
+; classic, ntsc, rgb for the rgb ppu of the arcade machines, or a .pal file with 64 or 512 colours
+palette = classic
+; Knobs for the ntsc palette. Hue is in degrees, and lower gamma darkens the mid tones
+ntsc_hue = 0
+ntsc_saturation = 1
+ntsc_contrast = 1
+ntsc_brightness = 1
+ntsc_gamma = 1.8
+; The ppu only draws 8 sprites on a line, no draws them all so games with more don't flicker
+sprite_limit = yes
+
+[audio]
+; Percent
+volume = 100
+; Milliseconds of audio to queue before dropping samples
+latency = 100
+
+; Holding the rewind hotkey steps back this many frames at a time, keeping at most budget megabytes of states
+[rewind]
+interval = 1
+budget = 32
+
+; Turbo buttons press and release their button while held, this many frames pressed then released
+[turbo]
+on = 2
+off = 2
+
+; Keys for each player's controller, several keys can be given separated by spaces.
+; Key names are as in piston, like A, D1, F1, Return, Space, LShift, NumPad8 or Up. MouseLeft is the left mouse button.
+; turbo_<button> keys are turbo buttons, and autofire_<button> keys switch turbo on for the button until pressed again
+[player1]
+up = Up
+down = Down
+left = Left
+right = Right
+a = A
+b = S
+select = Space
+start = Return
+turbo_a = Q
+turbo_b = W
+
+[player2]
+up = I
+down = K
+left = J
+right = L
+a = H
+b = G
+select = T
+start = Y
+
+[player3]
+up = NumPad8
+down = NumPad2
+left = NumPad4
+right = NumPad6
+a = NumPad9
+b = NumPad7
+select = NumPad1
+start = NumPad3
+
+[player4]
+up = F
+down = V
+left = C
+right = B
+a = X
+b = Z
+select = E
+start = R
+
+; Keys for the Power Pad and Family Trainer mat, buttons 1-4 are the top row, 5-8 the middle and 9-12 the bottom.
+; While a mat is plugged in, these keys press its buttons instead of any controller buttons they are also bound to
+[power_pad]
+button1 = Y
+button2 = U
+button3 = I
+button4 = O
+button5 = H
+button6 = J
+button7 = K
+button8 = L
+button9 = N
+button10 = M
+button11 = Comma
+button12 = Period
+
+; Buttons of SDL game controllers, named after an Xbox pad: a, b, x, y, back, guide, start,
+; leftshoulder, rightshoulder, dpup, dpdown, dpleft, dpright. The left stick also steers.
+; Pads go to the first free player, unless a playerN key names part of the pad's name
+[gamepad]
+up = dpup
+down = dpdown
+left = dpleft
+right = dpright
+a = b y
+b = a x
+select = back
+start = start
+turbo_a = rightshoulder
+turbo_b = leftshoulder
+; Out of 32767
+deadzone = 12000
+player1 =
+player2 =
+player3 =
+player4 =
+
+; 0-9 always pick the save state slot
+[hotkeys]
+save_state = F5
+load_state = F7
+rewind = Backspace
+swap_port_2 = F2
+four_score = F3
+swap_expansion = F4
+record_movie = F8
+record_movie_from_power_on = F9
+stop_movie = F10
+play_movie = F11
+movie_read_only = F12
+play_tape = F6
+record_tape = F1
+capture_keyboard = ScrollLock
+reset = Home
+power_cycle = End
+; Cycles through the built in palettes
+next_palette = PageUp
+; Switches printing every instruction the cpu runs on and off, like --trace
+trace =
+";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Hotkey {
+    SaveState,
+    LoadState,
+    Rewind,
+    SwapPort2,
+    FourScore,
+    SwapExpansion,
+    RecordMovie,
+    RecordMovieFromPowerOn,
+    StopMovie,
+    PlayMovie,
+    MovieReadOnly,
+    PlayTape,
+    RecordTape,
+    CaptureKeyboard,
+    Reset,
+    PowerCycle,
+    NextPalette,
+    Trace,
+}
+
+const HOTKEYS: [(&str, Hotkey); 18] = [
+    ("save_state", Hotkey::SaveState),
+    ("load_state", Hotkey::LoadState),
+    ("rewind", Hotkey::Rewind),
+    ("swap_port_2", Hotkey::SwapPort2),
+    ("four_score", Hotkey::FourScore),
+    ("swap_expansion", Hotkey::SwapExpansion),
+    ("record_movie", Hotkey::RecordMovie),
+    ("record_movie_from_power_on", Hotkey::RecordMovieFromPowerOn),
+    ("stop_movie", Hotkey::StopMovie),
+    ("play_movie", Hotkey::PlayMovie),
+    ("movie_read_only", Hotkey::MovieReadOnly),
+    ("play_tape", Hotkey::PlayTape),
+    ("record_tape", Hotkey::RecordTape),
+    ("capture_keyboard", Hotkey::CaptureKeyboard),
+    ("reset", Hotkey::Reset),
+    ("power_cycle", Hotkey::PowerCycle),
+    ("next_palette", Hotkey::NextPalette),
+    ("trace", Hotkey::Trace),
+];
+
+const CONTROLLER_BUTTONS: [(&str, u8); 8] = [
+    ("up", BUTTON_UP),
+    ("down", BUTTON_DOWN),
+    ("left", BUTTON_LEFT),
+    ("right", BUTTON_RIGHT),
+    ("a", BUTTON_A),
+    ("b", BUTTON_B),
+    ("select", BUTTON_SELECT),
+    ("start", BUTTON_START),
+];
+
+// The keys that can be bound, looked up by their piston name
+const KEYS: &[Key] = &[
+    Key::A, Key::B, Key::C, Key::D, Key::E, Key::F, Key::G, Key::H, Key::I, Key::J, Key::K, Key::L, Key::M,
+    Key::N, Key::O, Key::P, Key::Q, Key::R, Key::S, Key::T, Key::U, Key::V, Key::W, Key::X, Key::Y, Key::Z,
+    Key::D0, Key::D1, Key::D2, Key::D3, Key::D4, Key::D5, Key::D6, Key::D7, Key::D8, Key::D9,
+    Key::F1, Key::F2, Key::F3, Key::F4, Key::F5, Key::F6, Key::F7, Key::F8, Key::F9, Key::F10, Key::F11, Key::F12,
+    Key::NumPad0, Key::NumPad1, Key::NumPad2, Key::NumPad3, Key::NumPad4,
+    Key::NumPad5, Key::NumPad6, Key::NumPad7, Key::NumPad8, Key::NumPad9,
+    Key::NumPadDivide, Key::NumPadMultiply, Key::NumPadMinus, Key::NumPadPlus, Key::NumPadEnter, Key::NumPadPeriod,
+    Key::Up, Key::Down, Key::Left, Key::Right,
+    Key::Return, Key::Space, Key::Backspace, Key::Tab, Key::Escape, Key::Delete, Key::Insert,
+    Key::Home, Key::End, Key::PageUp, Key::PageDown, Key::CapsLock, Key::ScrollLock, Key::Pause, Key::PrintScreen,
+    Key::LCtrl, Key::LShift, Key::LAlt, Key::RCtrl, Key::RShift, Key::RAlt,
+    Key::Minus, Key::Equals, Key::LeftBracket, Key::RightBracket, Key::Backslash,
+    Key::Semicolon, Key::Quote, Key::Backquote, Key::Comma, Key::Period, Key::Slash,
+];
+
+// Sections of key = value pairs
+pub struct Ini {
+    sections: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl Ini {
+    pub fn parse(text: &str) -> Result<Ini, String> {
+        let mut ini = Ini { sections: BTreeMap::new() };
+        ini.merge(text)?;
+        Ok(ini)
+    }
+
+    // Values in text replace the ones already there
+    pub fn merge(&mut self, text: &str) -> Result<(), String> {
+        let mut section = String::new();
+
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with(';') || line.starts_with('#') {
+                continue;
+            }
+
+            if line.starts_with('[') && line.ends_with(']') {
+                section = line[1..line.len() - 1].trim().to_lowercase();
+                continue;
+            }
+
+            match line.find('=') {
+                Some(pos) => self.set(&section, &line[..pos], &line[pos + 1..]),
+                None => return Err(format!("Line {} is not a section or key = value: {}", i + 1, line)),
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn set(&mut self, section: &str, key: &str, val: &str) {
+        self.sections.entry(section.trim().to_lowercase()).or_insert_with(BTreeMap::new)
+            .insert(key.trim().to_lowercase(), val.trim().to_string());
+    }
+
+    pub fn get(&self, section: &str, key: &str) -> &str {
+        self.sections.get(section).and_then(|keys| keys.get(key)).map(|val| val.as_str()).unwrap_or("")
+    }
+
+    // Takes --section.key=value
+    pub fn set_override(&mut self, arg: &str) -> Result<(), String> {
+        let setting = if arg.starts_with("--") { &arg[2..] } else { arg };
+        let (name, val) = match setting.find('=') {
+            Some(pos) => (&setting[..pos], &setting[pos + 1..]),
+            None => return Err(format!("{} has no value, use --section.key=value", arg)),
+        };
+
+        match name.find('.') {
+            Some(pos) => {
+                self.set(&name[..pos], &name[pos + 1..], val);
+                Ok(())
+            },
+            None => Err(format!("{} has no section, use --section.key=value", arg)),
+        }
+    }
+}
+
+pub struct Settings {
+    pub rom: String,
+    pub ram_fill: RamFill,
+    pub scale: u32,
+    // A built in palette or a .pal file
+    pub palette: String,
+    pub ntsc: NtscSettings,
+    pub sprite_limit: bool,
+    pub volume: u32,
+    pub latency: u32,
+    pub rewind_interval: u32,
+    // Bytes
+    pub rewind_budget: usize,
+
+    // (key, player, button)
+    pub keys: Vec<(Button, usize, u8)>,
+    pub turbo_keys: Vec<(Button, usize, u8)>,
+    pub autofire_keys: Vec<(Button, usize, u8)>,
+    // (key, mat button number)
+    pub power_pad_keys: Vec<(Button, u8)>,
+    pub turbo_on_frames: u32,
+    pub turbo_off_frames: u32,
+
+    pub gamepad_buttons: Vec<(controller::Button, u8)>,
+    pub gamepad_turbo: Vec<(controller::Button, u8)>,
+    pub gamepad_deadzone: i16,
+    // Part of the name of the pad each player wants, if any
+    pub gamepad_players: Vec<String>,
+    pub hotkeys: Vec<(Key, Hotkey)>,
+}
+
+impl Settings {
+    // Writes the defaults out if there is no settings file yet
+    pub fn load(path: &str, overrides: &[String]) -> Result<Settings, String> {
+        let mut ini = Ini::parse(DEFAULT_SETTINGS)?;
+
+        if Path::new(path).exists() {
+            let text = fs::read_to_string(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+            ini.merge(&text).map_err(|e| format!("{}: {}", path, e))?;
+        } else {
+            match fs::write(path, DEFAULT_SETTINGS) {
+                Ok(()) => println!("Wrote default settings to {}", path),
+                Err(e) => println!("Could not write default settings to {}: {}", path, e),
+            }
+        }
+
+        for arg in overrides {
+            ini.set_override(arg)?;
+        }
+
+        Settings::from_ini(&ini)
+    }
+
+    pub fn from_ini(ini: &Ini) -> Result<Settings, String> {
+        let region = ini.get("general", "region");
+        if region != "ntsc" {
+            println!("Region {} is not supported, using ntsc", region);
+        }
+
+        let mut hotkeys = vec![];
+        for &(name, hotkey) in HOTKEYS.iter() {
+            for key in ini.get("hotkeys", name).split_whitespace() {
+                hotkeys.push((parse_key(key)?, hotkey));
+            }
+        }
+
+        let power_on = ini.get("general", "power_on");
+        let ram_fill = RamFill::parse(power_on).ok_or_else(|| format!("Unknown power on ram contents {}", power_on))?;
+
+        let palette = ini.get("video", "palette");
+
+        Ok(Settings {
+            rom: ini.get("general", "rom").to_string(),
+            ram_fill: ram_fill,
+            scale: parse_number(ini, "video", "scale")?.max(1),
+            palette: if palette.is_empty() { "classic".to_string() } else { palette.to_string() },
+            ntsc: NtscSettings {
+                hue: parse_float(ini, "video", "ntsc_hue")?,
+                saturation: parse_float(ini, "video", "ntsc_saturation")?,
+                contrast: parse_float(ini, "video", "ntsc_contrast")?,
+                brightness: parse_float(ini, "video", "ntsc_brightness")?,
+                gamma: parse_float(ini, "video", "ntsc_gamma")?,
+            },
+            sprite_limit: parse_bool(ini, "video", "sprite_limit")?,
+            volume: parse_number(ini, "audio", "volume")?.min(100),
+            latency: parse_number(ini, "audio", "latency")?,
+            rewind_interval: parse_number(ini, "rewind", "interval")?.max(1),
+            rewind_budget: parse_number(ini, "rewind", "budget")? as usize * 1024 * 1024,
+
+            keys: player_keys(ini, "")?,
+            turbo_keys: player_keys(ini, "turbo_")?,
+            autofire_keys: player_keys(ini, "autofire_")?,
+            power_pad_keys: power_pad_keys(ini)?,
+            turbo_on_frames: parse_number(ini, "turbo", "on")?,
+            turbo_off_frames: parse_number(ini, "turbo", "off")?,
+
+            gamepad_buttons: gamepad_buttons(ini, "")?,
+            gamepad_turbo: gamepad_buttons(ini, "turbo_")?,
+            gamepad_deadzone: parse_number(ini, "gamepad", "deadzone")?.min(32767) as i16,
+            gamepad_players: (1..5).map(|player| ini.get("gamepad", &format!("player{}", player)).to_string()).collect(),
+            hotkeys: hotkeys,
+        })
+    }
+
+    pub fn window_size(&self) -> [u32; 2] {
+        [256*self.scale, 240*self.scale]
+    }
+
+    pub fn hotkey(&self, key: Key) -> Option<Hotkey> {
+        self.hotkeys.iter().find(|binding| binding.0 == key).map(|binding| binding.1)
+    }
+}
+
+// Each controller button as prefix + its name, in all the player sections
+fn player_keys(ini: &Ini, prefix: &str) -> Result<Vec<(Button, usize, u8)>, String> {
+    let mut keys = vec![];
+    for player in 0..4 {
+        let section = format!("player{}", player + 1);
+        for &(name, button) in CONTROLLER_BUTTONS.iter() {
+            for key in ini.get(&section, &format!("{}{}", prefix, name)).split_whitespace() {
+                keys.push((parse_button(key)?, player, button));
+            }
+        }
+    }
+    Ok(keys)
+}
+
+fn power_pad_keys(ini: &Ini) -> Result<Vec<(Button, u8)>, String> {
+    let mut keys = vec![];
+    for number in 1..13 {
+        for key in ini.get("power_pad", &format!("button{}", number)).split_whitespace() {
+            keys.push((parse_button(key)?, number));
+        }
+    }
+    Ok(keys)
+}
+
+fn gamepad_buttons(ini: &Ini, prefix: &str) -> Result<Vec<(controller::Button, u8)>, String> {
+    let mut buttons = vec![];
+    for &(name, button) in CONTROLLER_BUTTONS.iter() {
+        for pad_button in ini.get("gamepad", &format!("{}{}", prefix, name)).split_whitespace() {
+            let pad_button = controller::Button::from_string(pad_button)
+                .ok_or_else(|| format!("Unknown gamepad button {}", pad_button))?;
+            buttons.push((pad_button, button));
+        }
+    }
+    Ok(buttons)
+}
+
+fn parse_number(ini: &Ini, section: &str, key: &str) -> Result<u32, String> {
+    let val = ini.get(section, key);
+    val.parse().map_err(|_| format!("{}.{} should be a number, not {}", section, key, val))
+}
+
+fn parse_float(ini: &Ini, section: &str, key: &str) -> Result<f32, String> {
+    let val = ini.get(section, key);
+    val.parse().map_err(|_| format!("{}.{} should be a number, not {}", section, key, val))
+}
+
+fn parse_bool(ini: &Ini, section: &str, key: &str) -> Result<bool, String> {
+    match ini.get(section, key) {
+        "yes" | "on" | "true" => Ok(true),
+        "no" | "off" | "false" => Ok(false),
+        val => Err(format!("{}.{} should be yes or no, not {}", section, key, val)),
+    }
+}
+
+fn parse_key(name: &str) -> Result<Key, String> {
+    KEYS.iter().cloned().find(|key| format!("{:?}", key).eq_ignore_ascii_case(name))
+        .ok_or_else(|| format!("Unknown key {}", name))
+}
+
+fn parse_button(name: &str) -> Result<Button, String> {
+    if name.eq_ignore_ascii_case("MouseLeft") {
+        Ok(Button::Mouse(MouseButton::Left))
+    } else {
+        parse_key(name).map(Button::Keyboard)
+    }
+}
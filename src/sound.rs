@@ -1,82 +1,147 @@
-use sdl2::audio::*;
-use sdl2;
 use memory::Mapper;
 use memory::Mem;
 use cpu::Cpu;
-use std::sync::Arc;
-use std::sync::Mutex;
-use sdl2::Sdl;
+use savestate::*;
+use std::io::Result;
 
-struct SoundData {
-    state_mut: Arc<Mutex<NesApuState>>,
-}
+impl NesSound {
+    pub fn new() -> NesSound {
+        let apu_state = NesApuState {
+            square: [
+                NesSquareChannel {
+                    envelope_timer_samples: 0,
+                    wave_timer_samples: 0,
+                    length_counter_samples: 0,
+                    sweep_counter_samples: 0,
+                    length_counter_orig: 0,
+                    length_counter_halt: false,
+                    volume: 0,
+                    constant_volume: false,
+                    timer: 0,
+                    sweep_enabled: false,
+                    sweep_period: 0,
+                    sweep_shift: 0,
+                    sweep_negate: false,
+                    mute: false,
+                }; 2
+            ],
+            dmc: NesDmcChannel {
+                irq_enable: false,
+                loop_sample: false,
+                rate: DMC_RATES[0],
+                output_level: 0,
+                sample_address: 0xC000,
+                sample_length: 1,
+
+                current_address: 0xC000,
+                bytes_remaining: 0,
+                sample_buffer: None,
+                shift_register: 0,
+                bits_remaining: 8,
+                silence: true,
+                timer: DMC_RATES[0],
+                irq_flag: false,
+                fetch_cycle: 0,
+            },
+        };
 
-impl AudioCallback for SoundData {
-    type Channel = u8;
+        NesSound {
+            state: apu_state,
+            samples: vec![],
+            sample_clock: 0,
+            last_cpu_count: 0,
 
-    fn callback(&mut self, out: &mut [u8]) {
-        let mut state = self.state_mut.lock().unwrap();
+            frame_counter_inhibit: false,
+            frame_counter_mode: 0,
+        }
+    }
 
-        for dst in out.iter_mut() {
-            *dst = state.tick() as u8;
+    // Samples are made in emulated time rather than when the host asks for them,
+    // so the length counters games read back don't depend on the audio device
+    pub fn tick(&mut self, cpu: &mut Cpu, _mapper: &mut Box<Mapper>) {
+        self.sample_clock += (cpu.count - self.last_cpu_count) as u64 * SAMPLES_PER_SECOND as u64;
+
+        // The dmc runs in cpu cycles, so that its sample fetches land on the right cycle for the dma unit
+        self.state.dmc.clock(self.last_cpu_count, cpu.count);
+        self.last_cpu_count = cpu.count;
+
+        while self.sample_clock >= CPU_CYCLES_PER_SECOND {
+            self.sample_clock -= CPU_CYCLES_PER_SECOND;
+            let sample = self.state.tick() as u8;
+            self.samples.push(sample);
         }
     }
-}
 
-pub fn init_audio(sdl: Sdl) -> NesSound {
-    let audio = sdl.audio().unwrap();
-
-    let desired_spec = AudioSpecDesired {
-        freq: Some(SAMPLES_PER_SECOND as i32),
-        channels: Some(1), // mono
-        samples: Some(128)
-    };
-
-    let apu_state = NesApuState {
-        square: [
-            NesSquareChannel {
-                envelope_timer_samples: 0,
-                wave_timer_samples: 0,
-                length_counter_samples: 0,
-                sweep_counter_samples: 0,
-                length_counter_orig: 0,
-                length_counter_halt: false,
-                volume: 0,
-                constant_volume: false,
-                timer: 0,
-                sweep_enabled: false,
-                sweep_period: 0,
-                sweep_shift: 0,
-                sweep_negate: false,
-                mute: false,
-            }; 2
-        ],
-    };
-
-    let arc = Arc::new(Mutex::new(apu_state));
-
-    let device = audio.open_playback(None, &desired_spec, |_spec| {
-        SoundData {
-            state_mut: arc.clone(),
+    // Reset silences every channel, like writing 0 to $4015
+    pub fn reset(&mut self) {
+        for channel in self.state.square.iter_mut() {
+            channel.length_counter_orig = 0;
+            channel.mute = true;
+        }
+        self.state.dmc.bytes_remaining = 0;
+        self.state.dmc.irq_flag = false;
+    }
+
+    // The address the dmc wants its next sample byte from, once its buffer has emptied
+    pub fn dmc_fetch(&self) -> Option<u16> {
+        let dmc = &self.state.dmc;
+        if dmc.sample_buffer.is_none() && dmc.bytes_remaining > 0 {
+            Some(dmc.current_address)
+        } else {
+            None
+        }
+    }
+
+    // The cpu cycle the dmc's buffer emptied on
+    pub fn dmc_fetch_cycle(&self) -> u32 {
+        self.state.dmc.fetch_cycle
+    }
+
+    // The byte the dma unit read for the dmc
+    pub fn dmc_fill(&mut self, cpu: &mut Cpu, val: u8) {
+        let dmc = &mut self.state.dmc;
+        dmc.sample_buffer = Some(val);
+        dmc.current_address = if dmc.current_address == 0xFFFF { 0x8000 } else { dmc.current_address + 1 };
+        dmc.bytes_remaining -= 1;
+
+        if dmc.bytes_remaining == 0 {
+            if dmc.loop_sample {
+                dmc.restart();
+            } else if dmc.irq_enable {
+                dmc.irq_flag = true;
+                cpu.irq();
+            }
         }
-    }).unwrap();
+    }
 
-    // Start playback
-    device.resume();
+    // Back to how it powers on, with the sample clock still in step with the cpu
+    pub fn power_cycle(&mut self) {
+        let power_on = NesSound::new();
+        self.state = power_on.state;
+        self.frame_counter_inhibit = power_on.frame_counter_inhibit;
+        self.frame_counter_mode = power_on.frame_counter_mode;
+    }
 
-    NesSound {
-        _audio: audio,
-        _device: device,
-        state_mut: arc,
+    pub fn start_frame(&mut self) {
+        self.samples.clear();
+    }
 
-        frame_counter_inhibit: false,
-        frame_counter_mode: 0,
+    // The cpu cycle count starts over every frame
+    pub fn end_frame(&mut self, frame_time: u32) {
+        self.last_cpu_count -= frame_time;
+        self.state.dmc.fetch_cycle = self.state.dmc.fetch_cycle.saturating_sub(frame_time);
+    }
+
+    // Unsigned 8 bit mono samples at SAMPLES_PER_SECOND, for the last frame that was run
+    pub fn samples(&self) -> &[u8] {
+        &self.samples
     }
 }
 
 #[derive(Debug)]
 struct NesApuState {
     square: [NesSquareChannel; 2],
+    dmc: NesDmcChannel,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -98,6 +163,74 @@ struct NesSquareChannel {
     mute: bool,
 }
 
+// Plays 1 bit delta encoded samples that it reads from memory through the dma unit
+// See https://wiki.nesdev.com/w/index.php/APU_DMC
+#[derive(Debug, Clone, Copy)]
+struct NesDmcChannel {
+    irq_enable: bool,
+    loop_sample: bool,
+    // In cpu cycles
+    rate: u16,
+    output_level: u8,
+    sample_address: u16,
+    sample_length: u16,
+
+    current_address: u16,
+    bytes_remaining: u16,
+    sample_buffer: Option<u8>,
+    shift_register: u8,
+    bits_remaining: u8,
+    silence: bool,
+    timer: u16,
+    irq_flag: bool,
+    fetch_cycle: u32,
+}
+
+impl NesDmcChannel {
+    fn restart(&mut self) {
+        self.current_address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    // Runs the timer over the cpu cycles from start to end
+    fn clock(&mut self, start: u32, end: u32) {
+        let mut cycle = start;
+        while end - cycle >= self.timer as u32 {
+            cycle += self.timer as u32;
+            self.timer = self.rate;
+            self.clock_output(cycle);
+        }
+        self.timer -= (end - cycle) as u16;
+    }
+
+    fn clock_output(&mut self, cycle: u32) {
+        if !self.silence {
+            if self.shift_register & 1 != 0 {
+                if self.output_level <= 125 { self.output_level += 2; }
+            } else {
+                if self.output_level >= 2 { self.output_level -= 2; }
+            }
+        }
+        self.shift_register >>= 1;
+        self.bits_remaining -= 1;
+
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.sample_buffer.take() {
+                Some(val) => {
+                    self.shift_register = val;
+                    self.silence = false;
+                    self.fetch_cycle = cycle;
+                },
+                None => self.silence = true,
+            }
+        }
+    }
+}
+
+// Timer periods in cpu cycles
+const DMC_RATES: [u16; 16] = [428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54];
+
 trait Channel {
     fn tick(&mut self) -> u32;
     fn length_counter(&self) -> u8;
@@ -160,54 +293,54 @@ impl NesApuState {
 
         // https://wiki.nesdev.com/w/index.php/APU_Mixer#Linear_Approximation
         sample *= 0.00752;
+        sample += 0.00335 * self.dmc.output_level as f64;
 
         (sample * 255.0) as u32
     }
 }
 
 pub struct NesSound {
-    _audio: sdl2::AudioSubsystem,
-    _device:  AudioDevice<SoundData>,
-    state_mut: Arc<Mutex<NesApuState>>,
+    state: NesApuState,
+    samples: Vec<u8>,
+    sample_clock: u64,
+    last_cpu_count: u32,
 
     frame_counter_inhibit: bool,
     frame_counter_mode: u8,
 }
 
-impl NesSound {
-    pub fn tick(&mut self, _cpu: &mut Cpu, _mapper: &mut Box<Mapper>) {
-    }
-}
-
 // Table stolen from https://github.com/andrew-hoffman/halfnes/blob/master/src/main/java/com/grapeshot/halfnes/APU.java
 const LENGTH_LOOKUP: [u8; 32] = [10, 254, 20, 2, 40, 4, 80, 6,
     160, 8, 60, 10, 14, 12, 26, 14, 12, 16, 24, 18, 48, 20, 96, 22,
     192, 24, 72, 26, 16, 28, 32, 30];
 // https://nesdoug.com/2015/12/02/14-intro-to-sound/
 // https://wiki.nesdev.com/w/index.php/APU
-const APU: f64 = 1789773.0/2.0;
+pub const CPU_CYCLES_PER_SECOND: u64 = 1789773;
+const APU: f64 = CPU_CYCLES_PER_SECOND as f64/2.0;
 const APU_CYCLES_PER_ENVELOPE_CLOCK: f64 = 3728.5;
 const APU_CYCLES_PER_SAMPLE: f64 = APU/SAMPLES_PER_SECOND as f64;
-const SAMPLES_PER_SECOND: u32 = 44100;
+pub const SAMPLES_PER_SECOND: u32 = 44100;
 
 impl Mem for NesSound {
     fn read(&mut self, _mapper: &mut Box<Mapper>, addr: u16) -> u8 {
         match addr as usize {
             0x4015 => {
-                let mut state = self.state_mut.lock().unwrap();
+                let state = &self.state;
 
                 let old_counter_inhibit = if self.frame_counter_inhibit {1} else {0};
                 self.frame_counter_inhibit = false;
                 (old_counter_inhibit << 6)
                     | (if state.square[0].length_counter() > 0 { 0b00000001 } else {0})
                     | (if state.square[1].length_counter() > 0 { 0b00000010 } else {0})
+                    | (if state.dmc.bytes_remaining > 0 { 0b00010000 } else {0})
+                    | (if state.dmc.irq_flag { 0b10000000 } else {0})
             }
             _ => 0
         }
     }
 
     fn write(&mut self, _mapper: &mut Box<Mapper>, addr: u16, val: u8) {
-        let mut state = self.state_mut.lock().unwrap();
+        let state = &mut self.state;
 
         match addr as usize {
             0x4000 | 0x4004 => {
@@ -243,6 +376,27 @@ impl Mem for NesSound {
                 channel.sweep_counter_samples = 0;
                 channel.mute = false;
             }
+            0x4010 => {
+                state.dmc.irq_enable = (val&0b10000000) != 0;
+                state.dmc.loop_sample = (val&0b01000000) != 0;
+                state.dmc.rate = DMC_RATES[val as usize & 0b1111];
+                if !state.dmc.irq_enable {
+                    state.dmc.irq_flag = false;
+                }
+            }
+            0x4011 => state.dmc.output_level = val&0b01111111,
+            0x4012 => state.dmc.sample_address = 0xC000 + val as u16 * 64,
+            0x4013 => state.dmc.sample_length = val as u16 * 16 + 1,
+            0x4015 => {
+                state.dmc.irq_flag = false;
+                if val&0b00010000 == 0 {
+                    state.dmc.bytes_remaining = 0;
+                } else if state.dmc.bytes_remaining == 0 {
+                    state.dmc.restart();
+                    // An empty buffer is filled straight away
+                    state.dmc.fetch_cycle = self.last_cpu_count;
+                }
+            }
             0x4017 => {
                 self.frame_counter_mode = (val&0b10000000)>>7;
                 self.frame_counter_inhibit = (val&0b01000000)!=0;
@@ -251,4 +405,114 @@ impl Mem for NesSound {
         }
 //        if [0x4004, 0x4006, 0x4007].contains(&addr) { println!("State: {:?}", *state); }
     }
-}
\ No newline at end of file
+}
+
+impl SaveState for NesSquareChannel {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_u32(self.envelope_timer_samples);
+        out.write_u32(self.wave_timer_samples);
+        out.write_u32(self.length_counter_samples);
+        out.write_u32(self.sweep_counter_samples);
+
+        out.write_u8(self.length_counter_orig);
+        out.write_bool(self.length_counter_halt);
+        out.write_u8(self.volume);
+        out.write_bool(self.constant_volume);
+        out.write_u16(self.timer);
+        out.write_bool(self.sweep_enabled);
+        out.write_u8(self.sweep_period);
+        out.write_u8(self.sweep_shift);
+        out.write_bool(self.sweep_negate);
+        out.write_bool(self.mute);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        self.envelope_timer_samples = input.read_u32()?;
+        self.wave_timer_samples = input.read_u32()?;
+        self.length_counter_samples = input.read_u32()?;
+        self.sweep_counter_samples = input.read_u32()?;
+
+        self.length_counter_orig = input.read_u8()?;
+        self.length_counter_halt = input.read_bool()?;
+        self.volume = input.read_u8()?;
+        self.constant_volume = input.read_bool()?;
+        self.timer = input.read_u16()?;
+        self.sweep_enabled = input.read_bool()?;
+        self.sweep_period = input.read_u8()?;
+        self.sweep_shift = input.read_u8()?;
+        self.sweep_negate = input.read_bool()?;
+        self.mute = input.read_bool()?;
+        Ok(())
+    }
+}
+
+impl SaveState for NesDmcChannel {
+    fn save_state(&self, out: &mut StateWriter) {
+        out.write_bool(self.irq_enable);
+        out.write_bool(self.loop_sample);
+        out.write_u16(self.rate);
+        out.write_u8(self.output_level);
+        out.write_u16(self.sample_address);
+        out.write_u16(self.sample_length);
+
+        out.write_u16(self.current_address);
+        out.write_u16(self.bytes_remaining);
+        out.write_bool(self.sample_buffer.is_some());
+        out.write_u8(self.sample_buffer.unwrap_or(0));
+        out.write_u8(self.shift_register);
+        out.write_u8(self.bits_remaining);
+        out.write_bool(self.silence);
+        out.write_u16(self.timer);
+        out.write_bool(self.irq_flag);
+        out.write_u32(self.fetch_cycle);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        self.irq_enable = input.read_bool()?;
+        self.loop_sample = input.read_bool()?;
+        self.rate = input.read_u16()?;
+        self.output_level = input.read_u8()?;
+        self.sample_address = input.read_u16()?;
+        self.sample_length = input.read_u16()?;
+
+        self.current_address = input.read_u16()?;
+        self.bytes_remaining = input.read_u16()?;
+        let has_sample = input.read_bool()?;
+        let sample = input.read_u8()?;
+        self.sample_buffer = if has_sample { Some(sample) } else { None };
+        self.shift_register = input.read_u8()?;
+        self.bits_remaining = input.read_u8()?;
+        self.silence = input.read_bool()?;
+        self.timer = input.read_u16()?;
+        self.irq_flag = input.read_bool()?;
+        self.fetch_cycle = input.read_u32()?;
+        Ok(())
+    }
+}
+
+impl SaveState for NesSound {
+    fn save_state(&self, out: &mut StateWriter) {
+        for channel in self.state.square.iter() {
+            channel.save_state(out);
+        }
+        self.state.dmc.save_state(out);
+        out.write_u64(self.sample_clock);
+        out.write_u32(self.last_cpu_count);
+
+        out.write_bool(self.frame_counter_inhibit);
+        out.write_u8(self.frame_counter_mode);
+    }
+
+    fn load_state(&mut self, input: &mut StateReader) -> Result<()> {
+        for channel in self.state.square.iter_mut() {
+            channel.load_state(input)?;
+        }
+        self.state.dmc.load_state(input)?;
+        self.sample_clock = input.read_u64()?;
+        self.last_cpu_count = input.read_u32()?;
+
+        self.frame_counter_inhibit = input.read_bool()?;
+        self.frame_counter_mode = input.read_u8()?;
+        Ok(())
+    }
+}
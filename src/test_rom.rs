@@ -0,0 +1,136 @@
+use ines::load_file;
+use nes::Nes;
+use std::io::Error;
+use std::io::ErrorKind;
+use std::io::Result;
+
+// See http://wiki.nesdev.com/w/index.php/Emulator_tests for the conventions used by the test roms
+#[derive(Debug, Clone, Copy)]
+pub enum ResultSource {
+    // $6000 holds the status, $6001-$6003 hold $DE $B0 $61 and the text output starts at $6004
+    StatusProtocol,
+    // Older roms write a result code to ram, then loop forever
+    Ram { addr: u16, pass: u8 },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum TestResult {
+    Passed(String),
+    Failed(u8, String),
+    TimedOut(String),
+}
+
+impl TestResult {
+    pub fn passed(&self) -> bool {
+        match *self {
+            TestResult::Passed(_) => true,
+            _ => false
+        }
+    }
+}
+
+pub struct TestRunner {
+    pub nes: Nes,
+    source: ResultSource,
+}
+
+const STATUS_RUNNING: u8 = 0x80;
+const STATUS_NEEDS_RESET: u8 = 0x81;
+const MAX_TEXT_LEN: u16 = 4096;
+// Roms asking for a reset want it at least 100ms later
+const RESET_DELAY_FRAMES: u32 = 6;
+
+impl TestRunner {
+    pub fn new(path: &str, source: ResultSource) -> Result<TestRunner> {
+        let (flags, prg, chr) = load_file(path)?;
+
+        if !Nes::supports_mapper(flags.mapper) {
+            return Err(Error::new(ErrorKind::InvalidData,
+                                  format!("Mapper {} is not supported", flags.mapper)));
+        }
+
+        Ok(TestRunner {
+            nes: Nes::new(prg, chr, flags.mapper, flags.prg_ram_size, flags.horiz_mirroring),
+            source: source,
+        })
+    }
+
+    // Used by roms like nestest that have an automated mode at a different entry point
+    pub fn start_at(&mut self, pc: u16) {
+        self.nes.cpu.jump(pc);
+    }
+
+    pub fn run(&mut self, max_frames: u32) -> TestResult {
+        let mut reset_countdown = None;
+
+        for _ in 0..max_frames {
+            self.nes.tick_headless();
+
+            if self.nes.cpu.is_jammed() {
+                break;
+            }
+
+            if let ResultSource::StatusProtocol = self.source {
+                if !self.has_signature() { continue; }
+
+                match self.nes.chipset.read(0x6000) {
+                    STATUS_RUNNING => (),
+                    STATUS_NEEDS_RESET => match reset_countdown {
+                        Some(0) => {
+                            self.nes.reset();
+                            reset_countdown = None;
+                        },
+                        Some(frames) => reset_countdown = Some(frames - 1),
+                        None => reset_countdown = Some(RESET_DELAY_FRAMES),
+                    },
+                    0 => return TestResult::Passed(self.read_text()),
+                    code => return TestResult::Failed(code, self.read_text()),
+                }
+            }
+        }
+
+        match self.source {
+            ResultSource::StatusProtocol => {
+                let text = if self.has_signature() { self.read_text() } else { String::new() };
+                match self.jam_message() {
+                    Some(message) => TestResult::TimedOut(format!("{}\n{}", message, text)),
+                    None => TestResult::TimedOut(text),
+                }
+            },
+            ResultSource::Ram { addr, pass } => {
+                let code = self.nes.chipset.read(addr);
+                // The result in ram can't be trusted if the rom never got as far as writing it
+                if let Some(message) = self.jam_message() {
+                    TestResult::Failed(code, message)
+                } else if code == pass {
+                    TestResult::Passed(String::new())
+                } else {
+                    TestResult::Failed(code, format!("${:04X} = ${:02X}", addr, code))
+                }
+            }
+        }
+    }
+
+    fn jam_message(&self) -> Option<String> {
+        self.nes.cpu.jammed_on.map(|(op, addr)| {
+            format!("Cpu jammed on opcode ${:02X} at ${:04X} before the test finished", op, addr)
+        })
+    }
+
+    fn has_signature(&mut self) -> bool {
+        self.nes.chipset.read(0x6001) == 0xDE
+            && self.nes.chipset.read(0x6002) == 0xB0
+            && self.nes.chipset.read(0x6003) == 0x61
+    }
+
+    fn read_text(&mut self) -> String {
+        let mut text = vec![];
+        for i in 0..MAX_TEXT_LEN {
+            let c = self.nes.chipset.read(0x6004 + i);
+            if c == 0 { break; }
+            text.push(c);
+        }
+
+        String::from_utf8_lossy(&text).trim().to_string()
+    }
+}
@@ -0,0 +1,71 @@
+use input::*;
+use memory::Mapper;
+use ppu::Ppu;
+use savestate::*;
+use std::any::Any;
+use std::io::Result;
+
+// The sensor stays lit for a while after the beam passes, see https://wiki.nesdev.com/w/index.php/Zapper
+const LIGHT_SCANLINES: u32 = 20;
+const LIGHT_BRIGHTNESS: u8 = 0x80;
+
+pub struct Zapper {
+    // In NES pixels, None when pointed away from the screen
+    pub aim: Option<(u32, u32)>,
+    pub trigger: bool,
+}
+
+impl Zapper {
+    pub fn new() -> Zapper {
+        Zapper {
+            aim: None,
+            trigger: false,
+        }
+    }
+
+    fn sees_light(&self, ppu: &mut Ppu, mapper: &mut Box<Mapper>) -> bool {
+        let (x, y) = match self.aim {
+            Some(aim) => aim,
+            None => return false
+        };
+
+        match ppu.current_scanline() {
+            Some(line) if line >= y && line < y + LIGHT_SCANLINES => ppu.pixel_brightness(x, y, mapper) >= LIGHT_BRIGHTNESS,
+            _ => false
+        }
+    }
+}
+
+impl InputDevice for Zapper {
+    fn name(&self) -> &'static str {
+        "zapper"
+    }
+
+    // Bit 3 is clear while light is seen, bit 4 is set while the trigger is held
+    fn read(&mut self, _: u16, ppu: &mut Ppu, mapper: &mut Box<Mapper>) -> u8 {
+        let light = if self.sees_light(ppu, mapper) { 0 } else { 0b0000_1000 };
+        let trigger = if self.trigger { 0b0001_0000 } else { 0 };
+        light | trigger
+    }
+
+    fn write(&mut self, _: u8) {
+    }
+
+    fn as_any(&self) -> &Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut Any {
+        self
+    }
+}
+
+// Like the controller buttons, the aim and trigger come from the frontend
+impl SaveState for Zapper {
+    fn save_state(&self, _: &mut StateWriter) {
+    }
+
+    fn load_state(&mut self, _: &mut StateReader) -> Result<()> {
+        Ok(())
+    }
+}
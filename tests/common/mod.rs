@@ -0,0 +1,76 @@
+// Shared by the integration tests, each of which only uses some of it
+#![allow(dead_code)]
+
+use nes_emulator::ines::{load_file, Flags};
+use nes_emulator::nes::Nes;
+use nes_emulator::power_on::RamFill;
+
+pub fn sound_test_rom() -> (Flags, Vec<u8>, Vec<u8>) {
+    load_file("assets/SNDTEST.NES").unwrap()
+}
+
+// The sound test rom switched on with the given ram contents, with other chr in place of its own if given
+pub fn load_sound_test(ram_fill: RamFill, chr: Option<Vec<u8>>) -> Nes {
+    let (flags, prg, rom_chr) = sound_test_rom();
+    let chr = chr.unwrap_or(rom_chr);
+    Nes::with_ram_fill(prg, chr, flags.mapper, flags.prg_ram_size, flags.horiz_mirroring, ram_fill)
+}
+
+// Like LDA $4016, which leaves the high byte of the address on the bus just before the read
+pub fn read_port(nes: &mut Nes, addr: u16) -> u8 {
+    nes.chipset.mem.open_bus = (addr >> 8) as u8;
+    nes.chipset.read(addr)
+}
+
+pub fn write(nes: &mut Nes, addr: u16, val: u8) {
+    nes.chipset.ppu.write_main(&mut nes.chipset.mapper, addr, val, &nes.cpu);
+}
+
+pub fn set_sprite(nes: &mut Nes, n: u8, y: u8, tile: u8, attr: u8, x: u8) {
+    write(nes, 0x2003, 4*n);
+    for &val in &[y, tile, attr, x] {
+        write(nes, 0x2004, val);
+    }
+}
+
+// Black for the backdrop and white for every other colour
+pub fn set_black_and_white_palette(nes: &mut Nes) {
+    write(nes, 0x2006, 0x3F);
+    write(nes, 0x2006, 0x00);
+    for i in 0..0x20 {
+        write(nes, 0x2007, if i % 4 == 0 { 0x0F } else { 0x30 });
+    }
+}
+
+// Runs the ppu alone to the end of the frame, so nothing the game does gets in the way
+pub fn run_frame(nes: &mut Nes) {
+    for count in nes.cpu.count..1789773/60 {
+        nes.cpu.count = count;
+        nes.chipset.ppu.tick(&mut nes.cpu, &mut nes.chipset.mapper);
+    }
+    nes.chipset.ppu.prepare_draw(&mut nes.chipset.mapper);
+}
+
+// Tile 1 is solid, tile 2 is a single pixel in its top left corner, and tiles 4 and 5 are the
+// halves of a tall sprite with a single pixel in the bottom left corner. The background is all
+// tile 1 and the sprites are off screen
+pub fn load_with_test_tiles(mask: u8) -> Nes {
+    let mut chr = vec![0; 0x2000];
+    for row in 0..8 {
+        chr[16 + row] = 0xFF;
+    }
+    chr[32] = 0x80;
+    chr[5*16 + 7] = 0x80;
+    let mut nes = load_sound_test(RamFill::Zeros, Some(chr));
+
+    for n in 0..64 {
+        set_sprite(&mut nes, n, 0xFF, 0xFF, 0, 0);
+    }
+    write(&mut nes, 0x2006, 0x20);
+    write(&mut nes, 0x2006, 0x00);
+    for _ in 0..32*30 {
+        write(&mut nes, 0x2007, 1);
+    }
+    write(&mut nes, 0x2001, mask);
+    nes
+}
@@ -0,0 +1,231 @@
+extern crate nes_emulator;
+
+mod common;
+
+use common::*;
+use nes_emulator::controller::*;
+use nes_emulator::family_keyboard::*;
+use nes_emulator::arkanoid::*;
+use nes_emulator::four_score::*;
+use nes_emulator::input::*;
+use nes_emulator::nes::Nes;
+use nes_emulator::power_pad::*;
+use nes_emulator::power_on::RamFill;
+
+fn read_bits(nes: &mut Nes, addr: u16, n: usize) -> Vec<u8> {
+    nes.chipset.write(0x4016, 1);
+    nes.chipset.write(0x4016, 0);
+    (0..n).map(|_| read_port(nes, addr)).collect()
+}
+
+fn read_report(nes: &mut Nes, addr: u16) -> Vec<u8> {
+    read_bits(nes, addr, 10)
+}
+
+fn report(bits: &[u8]) -> Vec<u8> {
+    bits.iter().map(|bit| 0x40 | bit).collect()
+}
+
+#[test]
+fn ports_report_their_own_controller() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.set_buttons(0, BUTTON_A | BUTTON_UP);
+    nes.chipset.set_buttons(1, BUTTON_B | BUTTON_RIGHT);
+
+    assert_eq!(read_report(&mut nes, 0x4016), vec![0x41, 0x40, 0x40, 0x40, 0x41, 0x40, 0x40, 0x40, 0x41, 0x41]);
+    assert_eq!(read_report(&mut nes, 0x4017), vec![0x40, 0x41, 0x40, 0x40, 0x40, 0x40, 0x40, 0x41, 0x41, 0x41]);
+}
+
+#[test]
+fn strobe_keeps_reporting_a() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.set_buttons(1, BUTTON_A);
+    nes.chipset.write(0x4016, 1);
+
+    assert_eq!(read_port(&mut nes, 0x4017), 0x41);
+    assert_eq!(read_port(&mut nes, 0x4017), 0x41);
+}
+
+#[test]
+fn devices_can_be_swapped() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.set_buttons(1, BUTTON_A);
+
+    let controller = nes.chipset.plug(1, Box::new(Unplugged));
+    assert_eq!(controller.name(), "controller");
+    assert_eq!(read_report(&mut nes, 0x4017), vec![0x40; 10]);
+    assert_eq!(nes.chipset.buttons(1), 0);
+
+    nes.chipset.plug(1, controller);
+    assert_eq!(nes.chipset.buttons(1), BUTTON_A);
+    assert_eq!(read_report(&mut nes, 0x4017)[0], 0x41);
+}
+
+#[test]
+fn states_need_the_same_devices() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    let state = nes.save_state();
+
+    nes.chipset.plug(1, Box::new(Unplugged));
+    assert!(nes.load_state(&state).is_err());
+
+    nes.chipset.plug(1, Box::new(Controller::new()));
+    assert!(nes.load_state(&state).is_ok());
+}
+
+#[test]
+fn four_score_reports_four_players_and_signature() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.plug(0, Box::new(FourScore::new(0)));
+    nes.chipset.plug(1, Box::new(FourScore::new(1)));
+
+    nes.chipset.set_buttons(0, BUTTON_A);
+    nes.chipset.set_buttons(1, BUTTON_B);
+    nes.chipset.set_buttons(2, BUTTON_RIGHT);
+    nes.chipset.set_buttons(3, BUTTON_START);
+
+    assert_eq!(read_bits(&mut nes, 0x4016, 26), report(&[
+        1, 0, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 0, 0, 0, 0, 1,
+        0, 0, 0, 1, 0, 0, 0, 0,
+        1, 1,
+    ]));
+    assert_eq!(read_bits(&mut nes, 0x4017, 26), report(&[
+        0, 1, 0, 0, 0, 0, 0, 0,
+        0, 0, 0, 1, 0, 0, 0, 0,
+        0, 0, 1, 0, 0, 0, 0, 0,
+        1, 1,
+    ]));
+}
+
+#[test]
+fn famicom_adapter_reports_players_3_and_4_on_bit_1() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.plug_expansion(Box::new(FamicomFourPlayerAdapter::new()));
+
+    nes.chipset.set_buttons(0, BUTTON_A);
+    nes.chipset.set_buttons(2, BUTTON_B);
+    nes.chipset.set_buttons(3, BUTTON_A | BUTTON_SELECT);
+
+    assert_eq!(read_bits(&mut nes, 0x4016, 3), vec![0x41, 0x42, 0x40]);
+    assert_eq!(read_bits(&mut nes, 0x4017, 3), vec![0x42, 0x40, 0x42]);
+}
+
+#[test]
+fn players_without_a_controller_are_ignored() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.set_buttons(2, BUTTON_A);
+    assert_eq!(nes.chipset.buttons(2), 0);
+    assert!(nes.chipset.controller(3).is_none());
+}
+
+#[test]
+fn arkanoid_shifts_out_inverted_knob_position() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.plug(1, Box::new(Arkanoid::new()));
+    {
+        let arkanoid = nes.chipset.device::<Arkanoid>(1).unwrap();
+        arkanoid.knob = 0b1010_0011;
+        arkanoid.button = true;
+    }
+
+    let bits: Vec<u8> = read_bits(&mut nes, 0x4017, 8).iter().map(|&val| (val >> 3) & 1).collect();
+    assert_eq!(bits, vec![0, 1, 0, 1, 1, 1, 0, 0]);
+    assert_eq!(read_port(&mut nes, 0x4017) & 0b0001_0000, 0b0001_0000);
+}
+
+#[test]
+fn famicom_arkanoid_uses_bit_1() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.plug_expansion(Box::new(Arkanoid::new_famicom()));
+    nes.chipset.expansion_device::<Arkanoid>().unwrap().aim(255);
+
+    let bits: Vec<u8> = read_bits(&mut nes, 0x4017, 8).iter().map(|&val| (val >> 1) & 1).collect();
+    let knob: u8 = bits.iter().fold(0, |knob, bit| (knob << 1) | (bit ^ 1));
+    assert_eq!(knob, KNOB_MAX);
+    assert_eq!(read_port(&mut nes, 0x4016) & 0b10, 0);
+}
+
+#[test]
+fn power_pad_reports_buttons_on_bits_3_and_4() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.plug(1, Box::new(PowerPad::new()));
+    nes.chipset.device::<PowerPad>(1).unwrap().buttons = power_pad_button(1) | power_pad_button(12) | power_pad_button(7);
+
+    let reads = read_bits(&mut nes, 0x4017, 9);
+    let bit_3: Vec<u8> = reads.iter().map(|&val| (val >> 3) & 1).collect();
+    let bit_4: Vec<u8> = reads.iter().map(|&val| (val >> 4) & 1).collect();
+    assert_eq!(bit_3, vec![0, 1, 0, 0, 0, 0, 0, 1, 1]);
+    assert_eq!(bit_4, vec![0, 0, 1, 0, 1, 1, 1, 1, 1]);
+}
+
+#[test]
+fn family_trainer_scans_rows() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.plug_expansion(Box::new(FamilyTrainer::new()));
+    nes.chipset.expansion_device::<FamilyTrainer>().unwrap().buttons = power_pad_button(1) | power_pad_button(8);
+
+    nes.chipset.write(0x4016, 0b011);
+    assert_eq!(read_port(&mut nes, 0x4017), 0x40 | 0b0_1110);
+    nes.chipset.write(0x4016, 0b101);
+    assert_eq!(read_port(&mut nes, 0x4017), 0x40 | 0b1_1100);
+    nes.chipset.write(0x4016, 0b110);
+    assert_eq!(read_port(&mut nes, 0x4017), 0x40 | 0b1_1110);
+}
+
+// Reads every half row the way Family BASIC scans the keyboard
+fn scan_keyboard(nes: &mut Nes) -> Vec<u8> {
+    nes.chipset.write(0x4016, 0b101);
+    let mut rows = vec![];
+    for _ in 0..9 {
+        nes.chipset.write(0x4016, 0b100);
+        rows.push(read_port(nes, 0x4017) & 0b1_1110);
+        nes.chipset.write(0x4016, 0b110);
+        rows.push(read_port(nes, 0x4017) & 0b1_1110);
+    }
+    rows
+}
+
+#[test]
+fn family_keyboard_scans_matrix() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.plug_expansion(Box::new(FamilyKeyboard::new()));
+    {
+        let keyboard = nes.chipset.expansion_device::<FamilyKeyboard>().unwrap();
+        assert!(keyboard.set_key("RETURN", true));
+        assert!(keyboard.set_key("SPACE", true));
+        assert!(!keyboard.set_key("HELP", true));
+    }
+
+    let mut expected = vec![0b1_1110; 18];
+    expected[0] = 0b1_1010;
+    expected[17] = 0b1_1010;
+    assert_eq!(scan_keyboard(&mut nes), expected);
+
+    nes.chipset.expansion_device::<FamilyKeyboard>().unwrap().set_key("RETURN", false);
+    expected[0] = 0b1_1110;
+    assert_eq!(scan_keyboard(&mut nes), expected);
+
+    // Disabled, the keyboard drives nothing
+    nes.chipset.write(0x4016, 0);
+    assert_eq!(read_port(&mut nes, 0x4017), 0x40);
+}
+
+#[test]
+fn turbo_follows_the_frame_count() {
+    let mut turbo = Turbo::new(2, 1);
+    turbo.held = BUTTON_A;
+
+    let presses: Vec<u8> = (0..5).map(|frame| turbo.apply(frame, BUTTON_UP)).collect();
+    assert_eq!(presses, vec![BUTTON_UP | BUTTON_A, BUTTON_UP | BUTTON_A, BUTTON_UP,
+                             BUTTON_UP | BUTTON_A, BUTTON_UP | BUTTON_A]);
+
+    // Released turbo presses are taken back out of the controller's buttons
+    turbo.held = 0;
+    assert_eq!(turbo.apply(5, BUTTON_UP | BUTTON_A), BUTTON_UP);
+
+    assert!(turbo.toggle_autofire(BUTTON_B));
+    assert_eq!(turbo.apply(7, 0), BUTTON_B);
+    assert!(!turbo.toggle_autofire(BUTTON_B));
+    assert_eq!(turbo.apply(9, BUTTON_B), 0);
+}
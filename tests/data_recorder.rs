@@ -0,0 +1,83 @@
+extern crate nes_emulator;
+
+mod common;
+
+use common::*;
+use nes_emulator::data_recorder::*;
+use nes_emulator::family_keyboard::FamilyKeyboard;
+use nes_emulator::power_on::RamFill;
+
+#[test]
+fn tape_round_trips_through_wav() {
+    let tape = Tape {
+        samples: vec![0, 1, 1, 0, 1],
+        sample_rate: 44100,
+    };
+
+    let wav = tape.to_wav();
+    assert_eq!(&wav[0..4], b"RIFF");
+    assert_eq!(Tape::from_wav(&wav).unwrap(), tape);
+    assert!(Tape::from_wav(b"not a wav file").is_err());
+}
+
+#[test]
+fn reads_16_bit_stereo_wav() {
+    let mut wav = vec![];
+    wav.extend_from_slice(b"RIFF\x2c\x00\x00\x00WAVEfmt ");
+    wav.extend_from_slice(&[16, 0, 0, 0, 1, 0, 2, 0, 0x22, 0x56, 0, 0, 0x88, 0x58, 1, 0, 4, 0, 16, 0]);
+    wav.extend_from_slice(b"data\x08\x00\x00\x00");
+    // Left channel is 0x1000 then -0x1000, the right channel is ignored
+    wav.extend_from_slice(&[0x00, 0x10, 0x00, 0xF0, 0x00, 0xF0, 0x00, 0x10]);
+
+    let tape = Tape::from_wav(&wav).unwrap();
+    assert_eq!(tape.sample_rate, 22050);
+    assert_eq!(tape.samples, vec![1, 0]);
+}
+
+// In cpu cycles, rounded up to a whole 4410 samples
+const TENTH_OF_A_SECOND: u32 = 178978;
+
+#[test]
+fn recorder_samples_in_emulated_time() {
+    let mut recorder = DataRecorder::new();
+    recorder.record();
+
+    // A square wave switching level every tenth of a second
+    for i in 0..4 {
+        recorder.set_output(i & 1);
+        recorder.tick(TENTH_OF_A_SECOND);
+    }
+    recorder.stop();
+    assert_eq!(recorder.tape.samples.len(), 4*4410);
+    assert!(recorder.tape.samples[..4410].iter().all(|&level| level == 0));
+    assert!(recorder.tape.samples[4410..2*4410].iter().all(|&level| level == 1));
+
+    let tape = recorder.tape.clone();
+    recorder.play(tape);
+    let mut levels = vec![];
+    for _ in 0..4 {
+        levels.push(recorder.input());
+        recorder.tick(TENTH_OF_A_SECOND);
+    }
+    assert_eq!(levels, vec![0, 1, 0, 1]);
+    assert_eq!(recorder.mode, TapeMode::Stopped);
+}
+
+#[test]
+fn keyboard_reads_tape_on_bit_1() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.plug_expansion(Box::new(FamilyKeyboard::new()));
+    let tape = Tape {
+        samples: vec![1; 44100],
+        sample_rate: 44100,
+    };
+    nes.chipset.expansion_device::<FamilyKeyboard>().unwrap().recorder.play(tape);
+    nes.tick();
+
+    nes.chipset.write(0x4016, 0b100);
+    assert_eq!(nes.chipset.read(0x4016) & 0b10, 0b10);
+
+    // Bit 2 low keeps the tape from being read
+    nes.chipset.write(0x4016, 0);
+    assert_eq!(nes.chipset.read(0x4016) & 0b10, 0);
+}
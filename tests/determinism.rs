@@ -0,0 +1,69 @@
+extern crate nes_emulator;
+
+use nes_emulator::controller::*;
+use nes_emulator::desync::*;
+use nes_emulator::power_on::RamFill;
+
+const ROM: &str = "assets/SNDTEST.NES";
+
+fn buttons(frame: u64) -> u8 {
+    match frame % 64 {
+        10..=13 => BUTTON_DOWN,
+        20..=23 => BUTTON_RIGHT,
+        30..=31 => BUTTON_A,
+        40..=43 => BUTTON_UP,
+        _ => 0
+    }
+}
+
+fn run(checker: &mut DesyncChecker, frames: u64) -> Option<Desync> {
+    for i in 0..frames {
+        if let Some(desync) = checker.step([buttons(i), 0]) {
+            return Some(desync);
+        }
+    }
+    None
+}
+
+#[test]
+fn runs_with_the_same_input_match() {
+    let mut checker = DesyncChecker::new(ROM, RamFill::Zeros, 0).unwrap();
+    assert_eq!(run(&mut checker, 300), None);
+}
+
+#[test]
+fn save_states_keep_the_machine_in_sync() {
+    let mut checker = DesyncChecker::new(ROM, RamFill::Zeros, 1).unwrap();
+    assert_eq!(run(&mut checker, 300), None);
+}
+
+#[test]
+fn seeded_random_power_on_stays_in_sync() {
+    let mut checker = DesyncChecker::new(ROM, RamFill::Random(1234), 1).unwrap();
+    assert!(checker.expected.chipset.mem.ram.iter().any(|&val| val != 0));
+    assert_eq!(run(&mut checker, 300), None);
+}
+
+#[test]
+fn audio_samples_match() {
+    let mut checker = DesyncChecker::new(ROM, RamFill::Zeros, 0).unwrap();
+    for i in 0..120 {
+        checker.step([buttons(i), 0]);
+        assert_eq!(checker.expected.chipset.sound.samples(), checker.actual.chipset.sound.samples());
+        let len = checker.expected.chipset.sound.samples().len();
+        assert!(len >= 734 && len <= 736, "{} samples in a frame", len);
+    }
+}
+
+#[test]
+fn reports_first_divergence() {
+    let mut checker = DesyncChecker::new(ROM, RamFill::Zeros, 0).unwrap();
+    run(&mut checker, 10);
+    checker.actual.chipset.mem.ram[0x0123] ^= 0x40;
+
+    let desync = compare(&checker.expected, &checker.actual).unwrap();
+    assert_eq!(desync.frame, 10);
+    assert_eq!(desync.component, "ram");
+    assert_eq!(desync.offset, 0x0123);
+    assert_eq!(desync.expected.map(|val| val ^ 0x40), desync.actual);
+}
@@ -0,0 +1,106 @@
+extern crate nes_emulator;
+
+mod common;
+
+use common::*;
+use nes_emulator::controller::*;
+use nes_emulator::dma::*;
+use nes_emulator::nes::Nes;
+use nes_emulator::power_on::RamFill;
+
+// Brings the apu up to the cpu's cycle count, like the main loop does after each instruction
+fn run_to(nes: &mut Nes, count: u32) {
+    nes.cpu.count = count;
+    nes.chipset.sound.tick(&mut nes.cpu, &mut nes.chipset.mapper);
+}
+
+fn read_oam(nes: &mut Nes, index: u8) -> u8 {
+    nes.chipset.ppu.write_main(&mut nes.chipset.mapper, 0x2003, index, &nes.cpu);
+    nes.chipset.read(0x2004)
+}
+
+fn start_dmc_sample(nes: &mut Nes) {
+    nes.chipset.write(0x4010, 0x0F);
+    nes.chipset.write(0x4012, 0);
+    nes.chipset.write(0x4013, 0);
+    nes.chipset.write(0x4015, 0b0001_0000);
+}
+
+#[test]
+fn oam_dma_copies_a_page_through_the_bus() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    for i in 0..256 {
+        nes.chipset.write(0x0200 + i, (i as u8).wrapping_mul(7) & 0b1110_0011);
+    }
+
+    run_to(&mut nes, 1000);
+    oam_dma(&mut nes.chipset, &mut nes.cpu, 0x02);
+    assert_eq!(nes.cpu.count, 1000 + 513);
+
+    for i in 0..=255u8 {
+        assert_eq!(read_oam(&mut nes, i), i.wrapping_mul(7) & 0b1110_0011);
+    }
+
+    // Starting on an odd cycle needs one more to line up
+    run_to(&mut nes, 1001);
+    oam_dma(&mut nes.chipset, &mut nes.cpu, 0x40);
+    assert_eq!(nes.cpu.count, 1001 + 514);
+}
+
+#[test]
+fn dmc_fetch_steals_cycles() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    run_to(&mut nes, 1000);
+    start_dmc_sample(&mut nes);
+    assert_eq!(nes.chipset.read(0x4015) & 0b0001_0000, 0b0001_0000);
+
+    dmc_dma(&mut nes.chipset, &mut nes.cpu, 1010);
+    assert_eq!(nes.cpu.count, 1000 + 4);
+    // The sample was a single byte
+    assert_eq!(nes.chipset.read(0x4015) & 0b0001_0000, 0);
+
+    // Nothing left to fetch
+    let count = nes.cpu.count;
+    dmc_dma(&mut nes.chipset, &mut nes.cpu, count);
+    assert_eq!(nes.cpu.count, count);
+}
+
+#[test]
+fn dmc_fetch_on_an_odd_cycle_takes_one_less() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    run_to(&mut nes, 1001);
+    start_dmc_sample(&mut nes);
+
+    dmc_dma(&mut nes.chipset, &mut nes.cpu, 1011);
+    assert_eq!(nes.cpu.count, 1001 + 3);
+    assert_eq!(nes.chipset.read(0x4015) & 0b0001_0000, 0);
+}
+
+#[test]
+fn dmc_fetch_during_oam_dma_takes_two_cycles() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    run_to(&mut nes, 1000);
+    start_dmc_sample(&mut nes);
+
+    oam_dma(&mut nes.chipset, &mut nes.cpu, 0x02);
+    assert_eq!(nes.cpu.count, 1000 + 513 + 2);
+    assert_eq!(nes.chipset.read(0x4015) & 0b0001_0000, 0);
+}
+
+#[test]
+fn dmc_fetch_on_a_controller_read_loses_a_button() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.set_buttons(0, BUTTON_A | BUTTON_B);
+
+    run_to(&mut nes, 1000);
+    nes.chipset.write(0x4016, 1);
+    nes.chipset.write(0x4016, 0);
+    start_dmc_sample(&mut nes);
+
+    // The read of $4016 was the last cycle of the instruction, just as the dmc wanted the bus
+    assert_eq!(nes.chipset.read(0x4016) & 1, 1);
+    dmc_dma(&mut nes.chipset, &mut nes.cpu, 1001);
+
+    // B was clocked out by the repeated read, so select comes next
+    assert_eq!(nes.chipset.read(0x4016) & 1, 0);
+}
@@ -0,0 +1,44 @@
+extern crate nes_emulator;
+
+use nes_emulator::controller::*;
+use nes_emulator::golden_frame::*;
+use std::env;
+use std::fs;
+use std::path::Path;
+
+const DIFF_DIR: &str = "target/golden_frames";
+
+// Set UPDATE_GOLDEN_FRAMES=1 to write the current output as the new reference frames
+fn check(name: &str, rom: &str, script: &[(u32, u8)], frames: u32) {
+    assert!(Path::new(rom).exists(), "{}: {} not found", name, rom);
+
+    let frame = run_frames(rom, script, frames).unwrap();
+    let reference = format!("tests/golden/{}.png", name);
+
+    if env::var("UPDATE_GOLDEN_FRAMES").is_ok() {
+        frame.save(&reference).unwrap();
+        return;
+    }
+
+    assert!(Path::new(&reference).exists(),
+            "{}: no reference frame at {}, run with UPDATE_GOLDEN_FRAMES=1 to make one", name, reference);
+
+    fs::create_dir_all(DIFF_DIR).unwrap();
+    let diff = format!("{}/{}.png", DIFF_DIR, name);
+
+    match compare_with_reference(&frame, &reference, &diff).unwrap() {
+        Comparison::Matches => (),
+        Comparison::Differs(n) => panic!("{}: {} pixels differ from the reference, see {}", name, n, diff),
+    }
+}
+
+#[test]
+fn sndtest_menu() {
+    check("sndtest_menu", "assets/SNDTEST.NES", &[], 60);
+}
+
+#[test]
+fn sndtest_menu_selection() {
+    check("sndtest_menu_selection", "assets/SNDTEST.NES",
+          &[(30, BUTTON_DOWN), (36, 0), (42, BUTTON_RIGHT), (48, 0)], 90);
+}
@@ -0,0 +1,201 @@
+extern crate nes_emulator;
+
+mod common;
+
+use common::*;
+use nes_emulator::controller::*;
+use nes_emulator::md5::md5;
+use nes_emulator::movie::*;
+use nes_emulator::nes::Nes;
+use nes_emulator::power_on::RamFill;
+
+const FM2: &str = "version 3
+emuVersion 22020
+rerecordCount 7
+palFlag 0
+romFilename SNDTEST
+romChecksum base64:1B2M2Y8AsgTpgAmY7PhCfg==
+guid 452DE2C3-EF43-2FA9-77AC-0677FC51543B
+fourscore 0
+microphone 0
+port0 1
+port1 1
+port2 0
+FDS 0
+NewPPU 0
+comment author someone
+|0|........|........||
+|0|R..U...A|........||
+|0|        |.L..T.B.||
+";
+
+fn load_with_checksum() -> (Nes, [u8; 16]) {
+    let (_, prg, chr) = sound_test_rom();
+    (load_sound_test(RamFill::Zeros, None), rom_checksum(&prg, &chr))
+}
+
+fn hex(digest: [u8; 16]) -> String {
+    digest.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[test]
+fn md5_matches_reference() {
+    assert_eq!(hex(md5(b"")), "d41d8cd98f00b204e9800998ecf8427e");
+    assert_eq!(hex(md5(b"abc")), "900150983cd24fb0d6963f7d28e17f72");
+    assert_eq!(hex(md5(b"12345678901234567890123456789012345678901234567890123456789012345678901234567890")),
+               "57edf4a22be3c955ac49da2e2107b67a");
+}
+
+#[test]
+fn parses_fm2() {
+    let movie = Movie::parse(FM2).unwrap();
+    assert_eq!(movie.rom_filename, "SNDTEST");
+    assert_eq!(movie.rom_checksum, Some(md5(b"")));
+    assert_eq!(movie.rerecord_count, 7);
+    assert_eq!(movie.comments, vec!["author someone".to_string()]);
+
+    let ports: Vec<[u8; 2]> = movie.frames.iter().map(|frame| frame.ports).collect();
+    assert_eq!(ports, vec![
+        [0, 0],
+        [BUTTON_RIGHT | BUTTON_UP | BUTTON_A, 0],
+        [0, BUTTON_LEFT | BUTTON_START | BUTTON_B],
+    ]);
+}
+
+#[test]
+fn fm2_round_trips() {
+    let mut movie = Movie::parse(FM2).unwrap();
+    movie.savestate = Some(vec![1, 2, 3, 4, 5]);
+    movie.ram_fill = Some(RamFill::Random(1234));
+
+    let text = movie.to_fm2();
+    assert!(text.contains("|0|R..U...A|........||"));
+
+    let parsed = Movie::parse(&text).unwrap();
+    assert_eq!(parsed.savestate, Some(vec![1, 2, 3, 4, 5]));
+    assert_eq!(parsed.ram_fill, Some(RamFill::Random(1234)));
+    assert_eq!(parsed.rom_checksum, movie.rom_checksum);
+    assert_eq!(parsed.guid, movie.guid);
+    assert_eq!(parsed.frames, movie.frames);
+}
+
+#[test]
+fn rejects_unsupported_movies() {
+    assert!(Movie::parse(&FM2.replace("fourscore 0", "fourscore 1")).is_err());
+    assert!(Movie::parse(&FM2.replace("palFlag 0", "palFlag 1")).is_err());
+    assert!(Movie::parse(&FM2.replace("port0 1", "port0 2")).is_err());
+}
+
+#[test]
+fn rejects_odd_length_hex() {
+    assert!(Movie::parse(&FM2.replace("FDS 0\n", "FDS 0\nsavestate 0x01020\n")).is_err());
+    assert_eq!(Movie::parse(&FM2.replace("FDS 0\n", "FDS 0\nsavestate 0x0102\n")).unwrap().savestate, Some(vec![1, 2]));
+}
+
+fn scripted_buttons(frame: u64) -> u8 {
+    match frame {
+        20..=25 => BUTTON_DOWN,
+        40..=45 => BUTTON_RIGHT,
+        _ => 0
+    }
+}
+
+fn run_movie(nes: &mut Nes, player: &mut MoviePlayer, frames: u64) {
+    for i in 0..frames {
+        let live = MovieFrame { commands: 0, ports: [scripted_buttons(i), 0] };
+        let frame = player.next_frame(nes.frame, live);
+        nes.chipset.set_buttons(0, frame.ports[0]);
+        nes.chipset.set_buttons(1, frame.ports[1]);
+        nes.tick_headless();
+    }
+}
+
+#[test]
+fn playback_reproduces_recording() {
+    let (mut nes, checksum) = load_with_checksum();
+    let mut recorder = MoviePlayer::record(Movie::new("SNDTEST", checksum), nes.frame);
+    run_movie(&mut nes, &mut recorder, 60);
+    let expected = nes.save_state();
+
+    let movie = Movie::parse(&recorder.movie.to_fm2()).unwrap();
+    assert_eq!(movie.frames.len(), 60);
+
+    let (mut nes, _) = load_with_checksum();
+    let mut player = MoviePlayer::play(movie, nes.frame, true);
+    for _ in 0..60 {
+        let frame = player.next_frame(nes.frame, MovieFrame::default());
+        nes.chipset.set_buttons(0, frame.ports[0]);
+        nes.tick_headless();
+    }
+
+    assert_eq!(player.mode, MovieMode::Playing);
+    assert_eq!(nes.save_state(), expected);
+
+    player.next_frame(nes.frame, MovieFrame::default());
+    assert_eq!(player.mode, MovieMode::Finished);
+}
+
+#[test]
+fn playback_from_embedded_state() {
+    let (mut nes, checksum) = load_with_checksum();
+    for _ in 0..10 {
+        nes.tick_headless();
+    }
+
+    let mut movie = Movie::new("SNDTEST", checksum);
+    movie.savestate = Some(nes.save_state());
+    let mut recorder = MoviePlayer::record(movie, nes.frame);
+    run_movie(&mut nes, &mut recorder, 50);
+    let expected = nes.save_state();
+
+    let movie = Movie::parse(&recorder.movie.to_fm2()).unwrap();
+    let (mut nes, _) = load_with_checksum();
+    nes.load_state(movie.savestate.as_ref().unwrap()).unwrap();
+
+    let mut player = MoviePlayer::play(movie, nes.frame, true);
+    for _ in 0..50 {
+        let frame = player.next_frame(nes.frame, MovieFrame::default());
+        nes.chipset.set_buttons(0, frame.ports[0]);
+        nes.tick_headless();
+    }
+    assert_eq!(nes.save_state(), expected);
+}
+
+#[test]
+fn loading_state_in_read_write_mode_rerecords() {
+    let (mut nes, checksum) = load_with_checksum();
+    let mut player = MoviePlayer::record(Movie::new("SNDTEST", checksum), nes.frame);
+    run_movie(&mut nes, &mut player, 30);
+    let state = nes.save_state();
+    run_movie(&mut nes, &mut player, 30);
+    assert_eq!(player.movie.frames.len(), 60);
+
+    player.set_read_only(true);
+    nes.load_state(&state).unwrap();
+    player.state_loaded(nes.frame);
+    assert_eq!(player.mode, MovieMode::Playing);
+    assert_eq!(player.movie.frames.len(), 60);
+    assert_eq!(player.movie.rerecord_count, 0);
+
+    player.set_read_only(false);
+    nes.load_state(&state).unwrap();
+    player.state_loaded(nes.frame);
+    assert_eq!(player.mode, MovieMode::Recording);
+    assert_eq!(player.movie.frames.len(), 30);
+    assert_eq!(player.movie.rerecord_count, 1);
+}
+
+#[test]
+fn rewinding_cuts_the_movie_without_rerecording() {
+    let (mut nes, checksum) = load_with_checksum();
+    let mut player = MoviePlayer::record(Movie::new("SNDTEST", checksum), nes.frame);
+    run_movie(&mut nes, &mut player, 30);
+    let state = nes.save_state();
+    run_movie(&mut nes, &mut player, 30);
+
+    nes.load_state(&state).unwrap();
+    player.rewound(nes.frame);
+    assert_eq!(player.mode, MovieMode::Recording);
+    assert_eq!(player.movie.frames.len(), 30);
+    assert_eq!(player.movie.rerecord_count, 0);
+}
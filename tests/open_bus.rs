@@ -0,0 +1,51 @@
+extern crate nes_emulator;
+
+mod common;
+
+use common::*;
+use nes_emulator::power_on::RamFill;
+
+#[test]
+fn unmapped_reads_return_the_last_bus_value() {
+    let mut nes = load_sound_test(RamFill::Ones, None);
+
+    nes.chipset.write(0x0000, 0x5A);
+    assert_eq!(nes.chipset.read(0x5000), 0x5A);
+    assert_eq!(nes.chipset.read(0x4018), 0x5A);
+    assert_eq!(nes.chipset.read(0x4000), 0x5A);
+
+    nes.chipset.write(0x0001, 0xA5);
+    assert_eq!(nes.chipset.read(0x4014), 0xA5);
+    assert_eq!(nes.chipset.read(0x4015) & 0b00100000, 0b00100000);
+}
+
+#[test]
+fn write_only_ppu_registers_return_the_io_latch() {
+    let mut nes = load_sound_test(RamFill::Ones, None);
+
+    // Power on filled oam with $FF
+    assert_eq!(nes.chipset.read(0x2004), 0xFF);
+    assert_eq!(nes.chipset.read(0x2000), 0xFF);
+    assert_eq!(nes.chipset.read(0x3FF5), 0xFF);
+
+    // The status only drives the top 3 bits
+    let status = nes.chipset.read(0x2002);
+    assert_eq!(status & 0b00011111, 0b00011111);
+    assert_eq!(nes.chipset.read(0x2006), status);
+}
+
+// Reading every register and unmapped address leaves the game running, and the bus still holds the last value read
+#[test]
+fn game_runs_after_reading_every_register() {
+    let mut nes = load_sound_test(RamFill::Ones, None);
+    for addr in 0x2000..0x6000 {
+        nes.chipset.read(addr);
+    }
+    for _ in 0..10 {
+        nes.tick_headless();
+    }
+    assert!(!nes.cpu.is_jammed());
+
+    let val = nes.chipset.read(0x0010);
+    assert_eq!(nes.chipset.read(0x5000), val);
+}
@@ -0,0 +1,97 @@
+extern crate nes_emulator;
+
+use nes_emulator::palette::*;
+
+fn pal_file(colours: usize) -> Vec<u8> {
+    (0..colours * 3).map(|i| i as u8).collect()
+}
+
+#[test]
+fn loads_64_colours() {
+    let palette = Palette::from_bytes(&pal_file(64)).unwrap();
+    assert_eq!(palette.colour(0x01, 0), [3, 4, 5]);
+    assert_eq!(palette.colour(0x3F, 0), [189, 190, 191]);
+}
+
+#[test]
+fn emphasis_from_64_colours_darkens_the_others() {
+    let palette = Palette::from_bytes(&pal_file(64)).unwrap();
+    let plain = palette.colour(0x21, 0);
+    let red = palette.colour(0x21, 0b001);
+    assert_eq!(red[0], plain[0]);
+    assert!(red[1] < plain[1] && red[2] < plain[2]);
+
+    // The blacks in the last two columns are left alone
+    assert_eq!(palette.colour(0x2E, 0b111), palette.colour(0x2E, 0));
+}
+
+#[test]
+fn loads_512_colours() {
+    let data = pal_file(512);
+    let palette = Palette::from_bytes(&data).unwrap();
+    let i = 3*64 + 5;
+    assert_eq!(palette.colour(0x05, 0b011), [data[i*3], data[i*3 + 1], data[i*3 + 2]]);
+}
+
+#[test]
+fn rejects_other_sizes() {
+    assert!(Palette::from_bytes(&pal_file(63)).is_err());
+    assert!(Palette::from_bytes(&[]).is_err());
+}
+
+#[test]
+fn built_in_palettes() {
+    for name in BUILT_IN.iter() {
+        assert!(Palette::built_in(name).is_some(), "{}", name);
+    }
+    assert!(Palette::built_in("missing").is_none());
+}
+
+#[test]
+fn rgb_ppu_emphasis_turns_a_channel_up() {
+    let palette = Palette::built_in("rgb").unwrap();
+    assert_eq!(palette.colour(0x0F, 0), [0, 0, 0]);
+    assert_eq!(palette.colour(0x0F, 0b100), [0, 0, 255]);
+    assert_eq!(palette.colour(0x30, 0), [255, 255, 255]);
+}
+
+#[test]
+fn ntsc_greys_and_black() {
+    let palette = Palette::ntsc(&NtscSettings::default());
+    assert_eq!(palette.colour(0x0F, 0), [0, 0, 0]);
+    assert_eq!(palette.colour(0x0D, 0), [0, 0, 0]);
+    let greys: Vec<[u8; 3]> = [0x00, 0x10, 0x20].iter().map(|&c| palette.colour(c, 0)).collect();
+    for grey in &greys {
+        assert!(grey[0] == grey[1] && grey[1] == grey[2], "{:?}", grey);
+    }
+    assert!(greys[0][0] < greys[1][0] && greys[1][0] < greys[2][0]);
+}
+
+#[test]
+fn ntsc_hues() {
+    let palette = Palette::ntsc(&NtscSettings::default());
+    let red = palette.colour(0x16, 0);
+    let green = palette.colour(0x1A, 0);
+    let blue = palette.colour(0x12, 0);
+    assert!(red[0] > red[1] && red[0] > red[2], "{:?}", red);
+    assert!(green[1] > green[0] && green[1] > green[2], "{:?}", green);
+    assert!(blue[2] > blue[0] && blue[2] > blue[1], "{:?}", blue);
+}
+
+#[test]
+fn ntsc_adjustments() {
+    let default = Palette::ntsc(&NtscSettings::default());
+
+    let grey = Palette::ntsc(&NtscSettings { saturation: 0.0, ..NtscSettings::default() });
+    let colour = grey.colour(0x16, 0);
+    assert!(colour[0] == colour[1] && colour[1] == colour[2], "{:?}", colour);
+
+    let shifted = Palette::ntsc(&NtscSettings { hue: 30.0, ..NtscSettings::default() });
+    assert!(shifted.colour(0x16, 0) != default.colour(0x16, 0));
+
+    let bright = Palette::ntsc(&NtscSettings { brightness: 1.2, ..NtscSettings::default() });
+    assert!(bright.colour(0x10, 0)[0] > default.colour(0x10, 0)[0]);
+
+    let red = default.colour(0x20, 0b001);
+    assert!(red[0] > red[2], "{:?}", red);
+}
@@ -0,0 +1,53 @@
+extern crate nes_emulator;
+
+mod common;
+
+use common::*;
+use nes_emulator::power_on::*;
+
+fn filled(ram_fill: RamFill, area: u8) -> Vec<u8> {
+    let mut data = vec![0x55; 16];
+    ram_fill.fill(area, &mut data);
+    data
+}
+
+#[test]
+fn fills_patterns() {
+    assert_eq!(filled(RamFill::Zeros, CPU_RAM), vec![0; 16]);
+    assert_eq!(filled(RamFill::Ones, CPU_RAM), vec![0xFF; 16]);
+    assert_eq!(filled(RamFill::Alternating, CPU_RAM)[..12].to_vec(),
+               vec![0, 0, 0, 0, 0xFF, 0xFF, 0xFF, 0xFF, 0, 0, 0, 0]);
+}
+
+#[test]
+fn random_fill_repeats_with_the_seed() {
+    let ram = filled(RamFill::Random(42), CPU_RAM);
+    assert_eq!(filled(RamFill::Random(42), CPU_RAM), ram);
+    assert!(filled(RamFill::Random(43), CPU_RAM) != ram);
+    assert!(filled(RamFill::Random(42), VRAM) != ram);
+}
+
+#[test]
+fn parses_fill_names() {
+    for &fill in [RamFill::Zeros, RamFill::Ones, RamFill::Alternating, RamFill::Random(7)].iter() {
+        assert_eq!(RamFill::parse(&fill.to_string()), Some(fill));
+    }
+    match RamFill::parse("random") {
+        Some(RamFill::Random(_)) => (),
+        other => panic!("Expected a random fill, got {:?}", other),
+    }
+    assert_eq!(RamFill::parse("noise"), None);
+}
+
+#[test]
+fn power_cycle_refills_ram() {
+    let mut nes = load_sound_test(RamFill::Random(99), None);
+    let ram = nes.chipset.mem.ram;
+    assert!(ram.iter().any(|&val| val != ram[0]));
+
+    for _ in 0..30 {
+        nes.tick_headless();
+    }
+    nes.power_cycle();
+    assert_eq!(nes.chipset.mem.ram[..], ram[..]);
+}
@@ -0,0 +1,87 @@
+extern crate nes_emulator;
+
+mod common;
+
+use common::*;
+use nes_emulator::nes::Nes;
+use nes_emulator::palette::Palette;
+
+// A solid white background on a black backdrop, and a solid white sprite at the left edge
+fn load_with_solid_tile() -> Nes {
+    let mut nes = load_with_test_tiles(0);
+    set_black_and_white_palette(&mut nes);
+    write(&mut nes, 0x2000, 0);
+    set_sprite(&mut nes, 0, 99, 1, 0, 0);
+    nes
+}
+
+// Draws a whole frame with the given mask
+fn draw(nes: &mut Nes, mask: u8) {
+    write(nes, 0x2001, mask);
+    nes.cpu.count = 0;
+    run_frame(nes);
+}
+
+fn rgb(nes: &Nes, x: u32, y: u32) -> [u8; 3] {
+    let p = nes.chipset.ppu.output_canvas.get_pixel(x, y);
+    [p[0], p[1], p[2]]
+}
+
+#[test]
+fn background_hidden_in_left_column() {
+    let mut nes = load_with_solid_tile();
+    draw(&mut nes, 0b00001000);
+    let black = rgb(&nes, 0, 50);
+    assert_eq!(rgb(&nes, 7, 50), black);
+    assert!(rgb(&nes, 8, 50) != black);
+
+    draw(&mut nes, 0b00001010);
+    assert!(rgb(&nes, 0, 50) != black);
+}
+
+#[test]
+fn sprites_hidden_in_left_column() {
+    let mut nes = load_with_solid_tile();
+    draw(&mut nes, 0b00010000);
+    let black = rgb(&nes, 100, 100);
+    assert_eq!(rgb(&nes, 0, 100), black);
+
+    draw(&mut nes, 0b00010100);
+    assert!(rgb(&nes, 0, 100) != black);
+}
+
+#[test]
+fn emphasis_darkens_the_other_colours() {
+    let mut nes = load_with_solid_tile();
+    draw(&mut nes, 0b00001010);
+    let white = rgb(&nes, 50, 50);
+
+    draw(&mut nes, 0b00101010);
+    let red = rgb(&nes, 50, 50);
+    assert_eq!(red[0], white[0]);
+    assert!(red[1] < white[1] && red[2] < white[2]);
+
+    draw(&mut nes, 0b11101010);
+    let dim = rgb(&nes, 50, 50);
+    assert!(dim[0] < white[0] && dim[1] < white[1] && dim[2] < white[2]);
+}
+
+#[test]
+fn frame_holds_colour_and_emphasis() {
+    let mut nes = load_with_solid_tile();
+    draw(&mut nes, 0b00101000);
+    assert_eq!(nes.chipset.ppu.frame[50*256 + 50], 0x30 | 0b001 << 6);
+    assert_eq!(nes.chipset.ppu.frame[50*256], 0x0F | 0b001 << 6);
+}
+
+#[test]
+fn changing_palette_converts_the_same_frame() {
+    let mut nes = load_with_solid_tile();
+    draw(&mut nes, 0b00001010);
+    let frame = nes.chipset.ppu.frame.clone();
+
+    nes.chipset.ppu.palette = Palette::built_in("rgb").unwrap();
+    nes.chipset.ppu.update_canvas();
+    assert_eq!(nes.chipset.ppu.frame, frame);
+    assert_eq!(rgb(&nes, 50, 50), [255, 255, 255]);
+}
@@ -0,0 +1,51 @@
+extern crate nes_emulator;
+
+mod common;
+
+use common::*;
+use nes_emulator::controller::*;
+use nes_emulator::rewind::RewindBuffer;
+use nes_emulator::power_on::RamFill;
+
+#[test]
+fn rewind_restores_captured_states_in_reverse() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    let mut rewind = RewindBuffer::new(3, 1024 * 1024);
+    let mut captured = vec![];
+
+    for frame in 1..=30 {
+        if frame == 10 {
+            nes.chipset.set_buttons(0, BUTTON_DOWN);
+        }
+
+        nes.tick_headless();
+        rewind.capture(&nes);
+        if frame % 3 == 0 {
+            captured.push(nes.save_state());
+        }
+    }
+    assert_eq!(rewind.len(), captured.len());
+
+    while let Some(expected) = captured.pop() {
+        assert!(rewind.rewind(&mut nes));
+        assert!(nes.save_state() == expected);
+    }
+    assert!(!rewind.rewind(&mut nes));
+}
+
+#[test]
+fn rewind_drops_oldest_states_over_budget() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.tick_headless();
+    let state_size = nes.save_state().len();
+
+    // Room for one full state and a few deltas
+    let mut rewind = RewindBuffer::new(1, state_size + 1024);
+    for _ in 0..100 {
+        nes.tick_headless();
+        rewind.capture(&nes);
+    }
+
+    assert!(rewind.len() > 1);
+    assert!(rewind.len() < 100);
+}
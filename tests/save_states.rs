@@ -0,0 +1,93 @@
+extern crate nes_emulator;
+
+mod common;
+
+use common::*;
+use nes_emulator::controller::*;
+use nes_emulator::power_on::RamFill;
+
+#[test]
+fn load_state_restores_machine() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    for _ in 0..30 {
+        nes.tick_headless();
+    }
+
+    let state = nes.save_state();
+
+    nes.chipset.set_buttons(0, BUTTON_DOWN);
+    for _ in 0..30 {
+        nes.tick_headless();
+    }
+    let expected_state = nes.save_state();
+    let expected_frame = nes.chipset.ppu.output_canvas.clone();
+
+    nes.load_state(&state).unwrap();
+    assert_eq!(nes.save_state(), state);
+
+    for _ in 0..30 {
+        nes.tick_headless();
+    }
+    assert_eq!(nes.save_state(), expected_state);
+    assert!(nes.chipset.ppu.output_canvas.pixels().eq(expected_frame.pixels()));
+}
+
+#[test]
+fn load_state_rejects_bad_data() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.tick_headless();
+    let before = nes.save_state();
+
+    assert!(nes.load_state(b"not a state").is_err());
+
+    let mut truncated = before.clone();
+    truncated.truncate(before.len() / 2);
+    assert!(nes.load_state(&truncated).is_err());
+
+    let mut wrong_version = before.clone();
+    wrong_version[4] += 1;
+    assert!(nes.load_state(&wrong_version).is_err());
+
+    assert_eq!(nes.save_state(), before);
+}
+
+#[test]
+fn reset_keeps_ram() {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    for _ in 0..30 {
+        nes.tick_headless();
+    }
+    let ram: Vec<u8> = (0..0x800).map(|addr| nes.chipset.read(addr)).collect();
+    let s = nes.cpu.get_s();
+
+    nes.reset();
+
+    let after: Vec<u8> = (0..0x800).map(|addr| nes.chipset.read(addr)).collect();
+    assert_eq!(after, ram);
+    // The cpu goes through the reset vector with the interrupt flag set, pushing nothing but moving the stack
+    assert_eq!(nes.cpu.get_pc(), nes.chipset.read16(0xFFFC));
+    assert_eq!(nes.cpu.get_s(), s.wrapping_sub(3));
+    assert_eq!(nes.cpu.get_p() & 0b100, 0b100);
+}
+
+#[test]
+fn power_cycle_starts_over() {
+    let mut fresh = load_sound_test(RamFill::Zeros, None);
+    for _ in 0..60 {
+        fresh.tick_headless();
+    }
+
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.set_buttons(0, BUTTON_DOWN);
+    for _ in 0..30 {
+        nes.tick_headless();
+    }
+    nes.chipset.set_buttons(0, 0);
+    nes.power_cycle();
+    assert_eq!(nes.frame, 30);
+
+    for _ in 0..60 {
+        nes.tick_headless();
+    }
+    assert!(nes.chipset.ppu.output_canvas.pixels().eq(fresh.chipset.ppu.output_canvas.pixels()));
+}
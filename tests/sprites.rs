@@ -0,0 +1,207 @@
+extern crate nes_emulator;
+
+mod common;
+
+use common::*;
+use nes_emulator::nes::Nes;
+use nes_emulator::power_on::RamFill;
+
+fn load_sprites_only() -> Nes {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+
+    // Sprites only, so everything else on screen is the backdrop
+    write(&mut nes, 0x2001, 0b00010100);
+    for n in 0..64 {
+        set_sprite(&mut nes, n, 0xFF, 0xFF, 0, 0);
+    }
+    nes
+}
+
+fn overflow(nes: &mut Nes) -> bool {
+    nes.chipset.ppu.read_main(&mut nes.chipset.mapper, 0x2002) & 0b00100000 != 0
+}
+
+#[test]
+fn nine_sprites_on_a_line_overflow() {
+    let mut nes = load_sprites_only();
+    for n in 0..9 {
+        set_sprite(&mut nes, n, 99, 0, 0, 16*n);
+    }
+    run_frame(&mut nes);
+    assert!(overflow(&mut nes));
+}
+
+#[test]
+fn eight_sprites_on_a_line_do_not_overflow() {
+    let mut nes = load_sprites_only();
+    for n in 0..8 {
+        set_sprite(&mut nes, n, 99, 0, 0, 16*n);
+    }
+    run_frame(&mut nes);
+    assert!(!overflow(&mut nes));
+}
+
+#[test]
+fn overflow_search_reads_the_wrong_byte() {
+    let mut nes = load_sprites_only();
+    for n in 0..8 {
+        set_sprite(&mut nes, n, 99, 0, 0, 16*n);
+    }
+    // Sprite 9 is off screen, but the search takes its tile number as its y
+    set_sprite(&mut nes, 9, 0xFF, 99, 0, 0);
+    run_frame(&mut nes);
+    assert!(overflow(&mut nes));
+}
+
+#[test]
+fn tall_sprites_overflow_on_more_lines() {
+    let mut nes = load_sprites_only();
+    for n in 0..8 {
+        set_sprite(&mut nes, n, 99, 0, 0, 16*n);
+    }
+    // Ends just above the others when 8 pixels tall
+    set_sprite(&mut nes, 8, 91, 0, 0, 0);
+    run_frame(&mut nes);
+    assert!(!overflow(&mut nes));
+
+    write(&mut nes, 0x2000, 0b00100000);
+    nes.cpu.count = 0;
+    run_frame(&mut nes);
+    assert!(overflow(&mut nes));
+}
+
+// How many pixels of a line differ from the backdrop, in groups of 16 across the screen
+fn sprite_pixels(nes: &Nes, line: u32) -> Vec<usize> {
+    let canvas = &nes.chipset.ppu.output_canvas;
+    let backdrop = canvas.get_pixel(255, line);
+    (0..16).map(|group| {
+        (0..16).filter(|&x| canvas.get_pixel(16*group + x, line) != backdrop).count()
+    }).collect()
+}
+
+fn draw_nine_sprites(sprite_limit: bool) -> Vec<usize> {
+    let mut nes = load_sprites_only();
+    let (_, _, chr) = sound_test_rom();
+    nes.chipset.ppu.sprite_limit = sprite_limit;
+
+    // A bright colour for the sprites on a dark backdrop
+    set_black_and_white_palette(&mut nes);
+
+    let tile = (0..256).find(|&t| chr[16*t] | chr[16*t + 8] != 0).unwrap() as u8;
+    for n in 0..9 {
+        set_sprite(&mut nes, n, 99, tile, 0, 16*n);
+    }
+    run_frame(&mut nes);
+    assert!(overflow(&mut nes));
+    sprite_pixels(&nes, 100)
+}
+
+#[test]
+fn only_eight_sprites_are_drawn_on_a_line() {
+    let pixels = draw_nine_sprites(true);
+    assert!(pixels[..8].iter().all(|&count| count > 0));
+    assert_eq!(pixels[8], 0);
+}
+
+#[test]
+fn all_sprites_are_drawn_without_the_limit() {
+    let pixels = draw_nine_sprites(false);
+    assert!(pixels[..9].iter().all(|&count| count > 0));
+    assert_eq!(pixels[9], 0);
+}
+
+const SHOW_ALL: u8 = 0b00011110;
+const CLIP_LEFT: u8 = 0b00011000;
+
+// The line and x of the first pixel where the flag is seen, to within the 3 dots of a cpu cycle
+fn sprite_0_hit(nes: &mut Nes) -> Option<(u32, u32)> {
+    for count in nes.cpu.count..1789773/60 {
+        nes.cpu.count = count;
+        nes.chipset.ppu.tick(&mut nes.cpu, &mut nes.chipset.mapper);
+        if nes.chipset.ppu.read_main(&mut nes.chipset.mapper, 0x2002) & 0b01000000 != 0 {
+            let line = count*3/341 - 22;
+            let dot = (count*3)%341;
+            return Some((line, dot - 1));
+        }
+    }
+    None
+}
+
+fn assert_hit(nes: &mut Nes, line: u32, x: u32) {
+    let (hit_line, hit_x) = sprite_0_hit(nes).expect("No sprite 0 hit");
+    assert_eq!(hit_line, line);
+    assert!(hit_x >= x && hit_x < x + 3, "Hit at x={}, expected {}", hit_x, x);
+}
+
+#[test]
+fn sprite_0_hits_on_the_first_overlapping_pixel() {
+    let mut nes = load_with_test_tiles(SHOW_ALL);
+    set_sprite(&mut nes, 0, 49, 1, 0, 100);
+    assert_hit(&mut nes, 50, 100);
+}
+
+#[test]
+fn sprite_0_hits_behind_the_background() {
+    let mut nes = load_with_test_tiles(SHOW_ALL);
+    set_sprite(&mut nes, 0, 49, 1, 0b00100000, 100);
+    assert_hit(&mut nes, 50, 100);
+}
+
+#[test]
+fn transparent_background_does_not_hit() {
+    let mut nes = load_with_test_tiles(SHOW_ALL);
+    write(&mut nes, 0x2006, 0x20);
+    write(&mut nes, 0x2006, 0x00);
+    for _ in 0..32*30 {
+        write(&mut nes, 0x2007, 0);
+    }
+    set_sprite(&mut nes, 0, 49, 1, 0, 100);
+    assert_eq!(sprite_0_hit(&mut nes), None);
+}
+
+#[test]
+fn sprite_0_hit_follows_flipping() {
+    for &(attr, line, x) in &[(0, 50, 100), (0b01000000, 50, 107), (0b10000000, 57, 100), (0b11000000, 57, 107)] {
+        let mut nes = load_with_test_tiles(SHOW_ALL);
+        set_sprite(&mut nes, 0, 49, 2, attr, 100);
+        assert_hit(&mut nes, line, x);
+    }
+}
+
+#[test]
+fn sprite_0_hit_in_tall_sprites() {
+    let mut nes = load_with_test_tiles(SHOW_ALL);
+    set_sprite(&mut nes, 0, 49, 4, 0, 100);
+    assert_eq!(sprite_0_hit(&mut nes), None);
+
+    let mut nes = load_with_test_tiles(SHOW_ALL);
+    write(&mut nes, 0x2000, 0b00100000);
+    set_sprite(&mut nes, 0, 49, 4, 0, 100);
+    assert_hit(&mut nes, 65, 100);
+}
+
+#[test]
+fn no_sprite_0_hit_in_clipped_left_column() {
+    let mut nes = load_with_test_tiles(CLIP_LEFT);
+    set_sprite(&mut nes, 0, 49, 1, 0, 0);
+    assert_eq!(sprite_0_hit(&mut nes), None);
+
+    let mut nes = load_with_test_tiles(CLIP_LEFT);
+    set_sprite(&mut nes, 0, 49, 1, 0, 4);
+    assert_hit(&mut nes, 50, 8);
+
+    let mut nes = load_with_test_tiles(SHOW_ALL);
+    set_sprite(&mut nes, 0, 49, 1, 0, 0);
+    assert_hit(&mut nes, 50, 0);
+}
+
+#[test]
+fn no_sprite_0_hit_at_x_255() {
+    let mut nes = load_with_test_tiles(SHOW_ALL);
+    set_sprite(&mut nes, 0, 49, 1, 0, 255);
+    assert_eq!(sprite_0_hit(&mut nes), None);
+
+    let mut nes = load_with_test_tiles(SHOW_ALL);
+    set_sprite(&mut nes, 0, 49, 1, 0, 254);
+    assert_hit(&mut nes, 50, 254);
+}
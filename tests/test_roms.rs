@@ -0,0 +1,104 @@
+extern crate nes_emulator;
+
+use nes_emulator::test_rom::*;
+use std::path::Path;
+
+// The test roms are not distributed with the emulator, so the tests that need them are ignored unless asked
+// for with cargo test -- --ignored. See tests/roms/README.md
+fn run(rom: &str, source: ResultSource, start: Option<u16>, max_frames: u32) {
+    let path = format!("tests/roms/{}", rom);
+    assert!(Path::new(&path).exists(), "{} not found, see tests/roms/README.md", path);
+
+    let mut runner = TestRunner::new(&path, source).unwrap();
+    if let Some(pc) = start {
+        runner.start_at(pc);
+    }
+
+    let result = runner.run(max_frames);
+    assert!(result.passed(), "{}: {:?}", rom, result);
+}
+
+fn blargg(rom: &str) {
+    run(rom, ResultSource::StatusProtocol, None, 60 * 60);
+}
+
+// Tests from 2005 and earlier report through $F8 instead of $6000
+fn blargg_2005(rom: &str) {
+    run(rom, ResultSource::Ram { addr: 0xF8, pass: 1 }, None, 60 * 20);
+}
+
+#[test]
+fn jamming_before_the_result_is_written_fails() {
+    // Zeroed ram already holds nestest's passing code, so only the jam tells them apart
+    let mut runner = TestRunner::new("assets/SNDTEST.NES", ResultSource::Ram { addr: 0x02, pass: 0 }).unwrap();
+    runner.nes.chipset.write(0x0300, 0x02);
+    runner.start_at(0x0300);
+
+    match runner.run(1) {
+        TestResult::Failed(0, message) => assert!(message.contains("jammed on opcode $02 at $0300"), "{}", message),
+        result => panic!("{:?}", result),
+    }
+}
+
+#[test]
+#[ignore]
+fn nestest() {
+    // Automated mode starts at $C000 and leaves the result for the official opcodes in $02
+    run("nestest.nes", ResultSource::Ram { addr: 0x02, pass: 0 }, Some(0xC000), 60);
+}
+
+#[test] #[ignore] fn instr_basics() { blargg("instr_test-v5/rom_singles/01-basics.nes"); }
+#[test] #[ignore] fn instr_implied() { blargg("instr_test-v5/rom_singles/02-implied.nes"); }
+#[test] #[ignore] fn instr_immediate() { blargg("instr_test-v5/rom_singles/03-immediate.nes"); }
+#[test] #[ignore] fn instr_zero_page() { blargg("instr_test-v5/rom_singles/04-zero_page.nes"); }
+#[test] #[ignore] fn instr_zp_xy() { blargg("instr_test-v5/rom_singles/05-zp_xy.nes"); }
+#[test] #[ignore] fn instr_absolute() { blargg("instr_test-v5/rom_singles/06-absolute.nes"); }
+#[test] #[ignore] fn instr_abs_xy() { blargg("instr_test-v5/rom_singles/07-abs_xy.nes"); }
+#[test] #[ignore] fn instr_ind_x() { blargg("instr_test-v5/rom_singles/08-ind_x.nes"); }
+#[test] #[ignore] fn instr_ind_y() { blargg("instr_test-v5/rom_singles/09-ind_y.nes"); }
+#[test] #[ignore] fn instr_branches() { blargg("instr_test-v5/rom_singles/10-branches.nes"); }
+#[test] #[ignore] fn instr_stack() { blargg("instr_test-v5/rom_singles/11-stack.nes"); }
+#[test] #[ignore] fn instr_jmp_jsr() { blargg("instr_test-v5/rom_singles/12-jmp_jsr.nes"); }
+#[test] #[ignore] fn instr_rts() { blargg("instr_test-v5/rom_singles/13-rts.nes"); }
+#[test] #[ignore] fn instr_rti() { blargg("instr_test-v5/rom_singles/14-rti.nes"); }
+#[test] #[ignore] fn instr_brk() { blargg("instr_test-v5/rom_singles/15-brk.nes"); }
+#[test] #[ignore] fn instr_special() { blargg("instr_test-v5/rom_singles/16-special.nes"); }
+
+#[test] #[ignore] fn ppu_vbl_basics() { blargg("ppu_vbl_nmi/rom_singles/01-vbl_basics.nes"); }
+#[test] #[ignore] fn ppu_vbl_set_time() { blargg("ppu_vbl_nmi/rom_singles/02-vbl_set_time.nes"); }
+#[test] #[ignore] fn ppu_vbl_clear_time() { blargg("ppu_vbl_nmi/rom_singles/03-vbl_clear_time.nes"); }
+#[test] #[ignore] fn ppu_nmi_control() { blargg("ppu_vbl_nmi/rom_singles/04-nmi_control.nes"); }
+#[test] #[ignore] fn ppu_nmi_timing() { blargg("ppu_vbl_nmi/rom_singles/05-nmi_timing.nes"); }
+#[test] #[ignore] fn ppu_suppression() { blargg("ppu_vbl_nmi/rom_singles/06-suppression.nes"); }
+#[test] #[ignore] fn ppu_nmi_on_timing() { blargg("ppu_vbl_nmi/rom_singles/07-nmi_on_timing.nes"); }
+#[test] #[ignore] fn ppu_nmi_off_timing() { blargg("ppu_vbl_nmi/rom_singles/08-nmi_off_timing.nes"); }
+#[test] #[ignore] fn ppu_even_odd_frames() { blargg("ppu_vbl_nmi/rom_singles/09-even_odd_frames.nes"); }
+#[test] #[ignore] fn ppu_even_odd_timing() { blargg("ppu_vbl_nmi/rom_singles/10-even_odd_timing.nes"); }
+
+#[test] #[ignore] fn sprite_hit_basics() { blargg_2005("sprite_hit_tests_2005.10.05/01.basics.nes"); }
+#[test] #[ignore] fn sprite_hit_alignment() { blargg_2005("sprite_hit_tests_2005.10.05/02.alignment.nes"); }
+#[test] #[ignore] fn sprite_hit_corners() { blargg_2005("sprite_hit_tests_2005.10.05/03.corners.nes"); }
+#[test] #[ignore] fn sprite_hit_flip() { blargg_2005("sprite_hit_tests_2005.10.05/04.flip.nes"); }
+#[test] #[ignore] fn sprite_hit_left_clip() { blargg_2005("sprite_hit_tests_2005.10.05/05.left_clip.nes"); }
+#[test] #[ignore] fn sprite_hit_right_edge() { blargg_2005("sprite_hit_tests_2005.10.05/06.right_edge.nes"); }
+#[test] #[ignore] fn sprite_hit_screen_bottom() { blargg_2005("sprite_hit_tests_2005.10.05/07.screen_bottom.nes"); }
+#[test] #[ignore] fn sprite_hit_double_height() { blargg_2005("sprite_hit_tests_2005.10.05/08.double_height.nes"); }
+#[test] #[ignore] fn sprite_hit_timing_basics() { blargg_2005("sprite_hit_tests_2005.10.05/09.timing_basics.nes"); }
+#[test] #[ignore] fn sprite_hit_timing_order() { blargg_2005("sprite_hit_tests_2005.10.05/10.timing_order.nes"); }
+#[test] #[ignore] fn sprite_hit_edge_timing() { blargg_2005("sprite_hit_tests_2005.10.05/11.edge_timing.nes"); }
+
+#[test] #[ignore] fn mmc3_clocking() { blargg("mmc3_test_2/rom_singles/1-clocking.nes"); }
+#[test] #[ignore] fn mmc3_details() { blargg("mmc3_test_2/rom_singles/2-details.nes"); }
+#[test] #[ignore] fn mmc3_a12_clocking() { blargg("mmc3_test_2/rom_singles/3-A12_clocking.nes"); }
+#[test] #[ignore] fn mmc3_scanline_timing() { blargg("mmc3_test_2/rom_singles/4-scanline_timing.nes"); }
+#[test] #[ignore] fn mmc3_rev_a() { blargg("mmc3_test_2/rom_singles/5-MMC3.nes"); }
+#[test] #[ignore] fn mmc3_rev_b() { blargg("mmc3_test_2/rom_singles/6-MMC3_alt.nes"); }
+
+#[test] #[ignore] fn apu_len_ctr() { blargg("apu_test/rom_singles/1-len_ctr.nes"); }
+#[test] #[ignore] fn apu_len_table() { blargg("apu_test/rom_singles/2-len_table.nes"); }
+#[test] #[ignore] fn apu_irq_flag() { blargg("apu_test/rom_singles/3-irq_flag.nes"); }
+#[test] #[ignore] fn apu_jitter() { blargg("apu_test/rom_singles/4-jitter.nes"); }
+#[test] #[ignore] fn apu_len_timing() { blargg("apu_test/rom_singles/5-len_timing.nes"); }
+#[test] #[ignore] fn apu_irq_flag_timing() { blargg("apu_test/rom_singles/6-irq_flag_timing.nes"); }
+#[test] #[ignore] fn apu_dmc_basics() { blargg("apu_test/rom_singles/7-dmc_basics.nes"); }
+#[test] #[ignore] fn apu_dmc_rates() { blargg("apu_test/rom_singles/8-dmc_rates.nes"); }
@@ -0,0 +1,97 @@
+extern crate nes_emulator;
+
+mod common;
+
+use common::*;
+use nes_emulator::nes::Nes;
+use nes_emulator::palette::{self, Palette};
+use nes_emulator::power_on::RamFill;
+use nes_emulator::zapper::Zapper;
+
+fn load_with_zapper() -> Nes {
+    let mut nes = load_sound_test(RamFill::Zeros, None);
+    nes.chipset.plug(1, Box::new(Zapper::new()));
+
+    // The menu is white text on a dark background
+    for _ in 0..60 {
+        nes.tick_headless();
+    }
+    nes
+}
+
+fn luma(nes: &Nes, x: u32, y: u32) -> u8 {
+    palette::luma(nes.chipset.ppu.frame[(y*256 + x) as usize])
+}
+
+fn find_pixel(nes: &Nes, bright: bool) -> (u32, u32) {
+    for y in 40..200 {
+        for x in 0..256 {
+            if (luma(nes, x, y) >= 0xC0) == bright && (bright || luma(nes, x, y) < 0x20) {
+                return (x, y);
+            }
+        }
+    }
+    panic!("No {} pixel on screen", if bright { "bright" } else { "dark" });
+}
+
+// Runs the next frame up to the given visible line, then reads $4017
+fn read_at_scanline(nes: &mut Nes, line: u32) -> u8 {
+    loop {
+        nes.cpu.tick(&mut nes.chipset);
+        nes.chipset.ppu.tick(&mut nes.cpu, &mut nes.chipset.mapper);
+
+        if nes.chipset.ppu.current_scanline() == Some(line) {
+            return nes.chipset.read(0x4017);
+        }
+    }
+}
+
+fn aim(nes: &mut Nes, aim: Option<(u32, u32)>, trigger: bool) {
+    let zapper = nes.chipset.device::<Zapper>(1).unwrap();
+    zapper.aim = aim;
+    zapper.trigger = trigger;
+}
+
+#[test]
+fn sees_bright_pixels_as_the_beam_passes() {
+    let mut nes = load_with_zapper();
+    let (x, y) = find_pixel(&nes, true);
+    aim(&mut nes, Some((x, y)), false);
+
+    assert_eq!(read_at_scanline(&mut nes, y - 4) & 0b0000_1000, 0b0000_1000);
+    assert_eq!(read_at_scanline(&mut nes, y + 2) & 0b0000_1000, 0);
+    assert_eq!(read_at_scanline(&mut nes, y + 30) & 0b0000_1000, 0b0000_1000);
+}
+
+#[test]
+fn light_does_not_depend_on_the_palette() {
+    let mut nes = load_with_zapper();
+    let (x, y) = find_pixel(&nes, true);
+    aim(&mut nes, Some((x, y)), false);
+
+    // Everything on screen is black
+    nes.chipset.ppu.palette = Palette::from_bytes(&[0; 192]).unwrap();
+    assert_eq!(read_at_scanline(&mut nes, y + 2) & 0b0000_1000, 0);
+}
+
+#[test]
+fn dark_pixels_and_offscreen_are_not_lit() {
+    let mut nes = load_with_zapper();
+    let (x, y) = find_pixel(&nes, false);
+    aim(&mut nes, Some((x, y)), false);
+    assert_eq!(read_at_scanline(&mut nes, y + 2) & 0b0000_1000, 0b0000_1000);
+
+    nes.tick_headless();
+    aim(&mut nes, None, false);
+    assert_eq!(read_at_scanline(&mut nes, y + 2) & 0b0000_1000, 0b0000_1000);
+}
+
+#[test]
+fn reports_trigger() {
+    let mut nes = load_with_zapper();
+    aim(&mut nes, None, true);
+    assert_eq!(read_port(&mut nes, 0x4017), 0x40 | 0b0001_1000);
+
+    aim(&mut nes, None, false);
+    assert_eq!(read_port(&mut nes, 0x4017), 0x40 | 0b0000_1000);
+}